@@ -0,0 +1,80 @@
+/// Rockchip's CRC32 variant (`RKCRC` in the vendor tools): polynomial
+/// 0x04c10db7, MSB first, zero initial value and no final xor.
+const RKCRC_POLY: u32 = 0x04c1_0db7;
+
+const RKCRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = (i as u32) << 24;
+        let mut bit = 0;
+        while bit < 8 {
+            c = if c & 0x8000_0000 != 0 {
+                (c << 1) ^ RKCRC_POLY
+            } else {
+                c << 1
+            };
+            bit += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+};
+
+/// Incremental Rockchip CRC32, so large images can be hashed while streaming.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct RkCrc32 {
+    crc: u32,
+}
+
+impl RkCrc32 {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for &b in data {
+            self.crc = (self.crc << 8) ^ RKCRC_TABLE[((self.crc >> 24) as u8 ^ b) as usize];
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        self.crc
+    }
+}
+
+/// Rockchip CRC32 of a whole buffer.
+pub fn rkcrc32(data: &[u8]) -> u32 {
+    let mut crc = RkCrc32::new();
+    crc.update(data);
+    crc.finish()
+}
+
+/// Reflected polynomial of the standard CRC-32 used by GPT.
+const CRC32_POLY: u32 = 0xedb8_8320;
+
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            c = if c & 1 != 0 { (c >> 1) ^ CRC32_POLY } else { c >> 1 };
+            bit += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+};
+
+/// Standard CRC-32 (IEEE 802.3), as used in GPT headers.
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in data {
+        crc = (crc >> 8) ^ CRC32_TABLE[((crc as u8) ^ b) as usize];
+    }
+    !crc
+}
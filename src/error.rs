@@ -0,0 +1,73 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+use crate::path::PathRejection;
+
+/// Errors returned by the library. Offsets are relative to the start of the input.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum Error {
+    #[error("{}: {source}", path.display())]
+    File {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("Unknown signature: {signature:02x?}")]
+    UnknownSignature { signature: Vec<u8> },
+    #[error("{what} truncated at offset {offset:#x}: need {needed} bytes, {available} available")]
+    Truncated {
+        what: String,
+        offset: u64,
+        needed: u64,
+        available: u64,
+    },
+    #[error("{what}: bad magic {found:02x?} at offset {offset:#x}, expected {expected:02x?}")]
+    BadMagic {
+        what: &'static str,
+        offset: u64,
+        expected: &'static [u8],
+        found: Vec<u8>,
+    },
+    #[error("{what} at {offset:#x} (size {size}) lies outside the {limit}-byte input")]
+    OffsetOutOfBounds {
+        what: String,
+        offset: u64,
+        size: u64,
+        limit: u64,
+    },
+    #[error("{what} checksum mismatch: image says {expected}, computed {actual}")]
+    ChecksumMismatch {
+        what: &'static str,
+        expected: String,
+        actual: String,
+    },
+    #[error("{what} checksum missing, cannot verify image")]
+    ChecksumMissing { what: &'static str },
+    #[error("RKAF header declares {count} partitions: {reason}")]
+    InvalidPartCount { count: u32, reason: String },
+    #[error("refusing to write {path:?}: {reason}")]
+    UnsafePath { path: String, reason: PathRejection },
+    #[error("{format} images are not supported: {reason}")]
+    Unsupported { format: &'static str, reason: &'static str },
+    #[error("{0}")]
+    Invalid(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Attaches the file path to I/O errors.
+pub(crate) trait PathContext<T> {
+    fn with_path(self, path: impl AsRef<Path>) -> Result<T>;
+}
+
+impl<T> PathContext<T> for io::Result<T> {
+    fn with_path(self, path: impl AsRef<Path>) -> Result<T> {
+        self.map_err(|source| Error::File {
+            path: path.as_ref().to_path_buf(),
+            source,
+        })
+    }
+}
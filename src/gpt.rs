@@ -0,0 +1,207 @@
+use std::io::{Seek, SeekFrom, Write};
+use crate::crc::crc32;
+use crate::mtdparts::{PartitionTable, SECTOR_SIZE};
+use crate::{Error, Result};
+
+const GPT_SIGNATURE: &[u8] = b"EFI PART";
+const GPT_REVISION: u32 = 0x0001_0000;
+const GPT_HEADER_LEN: usize = 92;
+const GPT_ENTRIES: usize = 128;
+const GPT_ENTRY_LEN: usize = 128;
+/// Sectors taken by the partition entry array.
+const ENTRY_SECTORS: u64 = (GPT_ENTRIES * GPT_ENTRY_LEN) as u64 / SECTOR_SIZE;
+/// Protective MBR, primary header and entries.
+pub const GPT_PRIMARY_SECTORS: u64 = 2 + ENTRY_SECTORS;
+/// Backup entries and header at the end of the disk.
+pub const GPT_BACKUP_SECTORS: u64 = ENTRY_SECTORS + 1;
+/// Partition names are at most 36 UTF-16 code units.
+const MAX_NAME_UNITS: usize = 36;
+/// "Linux filesystem data", the type Rockchip tools give every partition.
+const LINUX_DATA_GUID: &str = "0fc63daf-8483-4772-8e79-3d69d8477de4";
+
+/// A GUID partition table for one disk: what goes into its first
+/// `GPT_PRIMARY_SECTORS` and last `GPT_BACKUP_SECTORS` sectors.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Gpt {
+    pub disk_sectors: u64,
+    /// Protective MBR, primary header and entries, written at LBA 0.
+    pub primary: Vec<u8>,
+    /// Entries and backup header, written at `backup_lba()`.
+    pub backup: Vec<u8>,
+}
+
+impl Gpt {
+    /// Lays out `table` on a disk of `disk_size` bytes. Partitions keep their
+    /// `mtdparts` start sector, the `grow` one (or one with size `-`) ends at the
+    /// last usable sector. Partitions without a `uuid:` annotation, and the disk
+    /// itself, get a name-based GUID so the same input always gives the same table.
+    pub fn from_table(table: &PartitionTable, disk_size: u64) -> Result<Self> {
+        let disk_sectors = disk_size / SECTOR_SIZE;
+        if disk_sectors < GPT_PRIMARY_SECTORS + GPT_BACKUP_SECTORS + 1 {
+            return Err(Error::Invalid(format!("disk of {} bytes is too small for a GPT", disk_size)));
+        }
+        if table.partitions.len() > GPT_ENTRIES {
+            return Err(Error::Invalid(format!(
+                "{} partitions do not fit in a GPT, at most {} are supported",
+                table.partitions.len(),
+                GPT_ENTRIES
+            )));
+        }
+        let first_usable = GPT_PRIMARY_SECTORS;
+        let last_usable = disk_sectors - GPT_BACKUP_SECTORS - 1;
+        let seed = table.to_string();
+        let type_guid = parse_guid(LINUX_DATA_GUID)?;
+
+        let mut entries = vec![0u8; GPT_ENTRIES * GPT_ENTRY_LEN];
+        let mut ranges = Vec::new();
+        for (partition, entry) in table.partitions.iter().zip(entries.chunks_mut(GPT_ENTRY_LEN)) {
+            let invalid = |what: String| Error::Invalid(format!("partition {}: {}", partition.name, what));
+            let first = partition.start;
+            let last = match partition.size {
+                Some(size) if !partition.grow => first
+                    .checked_add(size)
+                    .filter(|_| size > 0)
+                    .ok_or_else(|| invalid(format!("invalid size {:#x}", size)))?
+                    - 1,
+                _ => last_usable,
+            };
+            if first < first_usable || last > last_usable || first > last {
+                return Err(invalid(format!(
+                    "sectors {:#x}-{:#x} outside the usable range {:#x}-{:#x}",
+                    first, last, first_usable, last_usable
+                )));
+            }
+            let name: Vec<u16> = partition.name.encode_utf16().collect();
+            if name.len() > MAX_NAME_UNITS {
+                return Err(invalid(format!("name longer than {} characters", MAX_NAME_UNITS)));
+            }
+            let guid = match &partition.uuid {
+                Some(uuid) => parse_guid(uuid)?,
+                None => name_guid(&seed, &partition.name),
+            };
+
+            entry[0..16].copy_from_slice(&to_gpt_guid(&type_guid));
+            entry[16..32].copy_from_slice(&to_gpt_guid(&guid));
+            entry[32..40].copy_from_slice(&first.to_le_bytes());
+            entry[40..48].copy_from_slice(&last.to_le_bytes());
+            for (i, unit) in name.iter().enumerate() {
+                entry[56 + 2 * i..58 + 2 * i].copy_from_slice(&unit.to_le_bytes());
+            }
+            ranges.push((first, last, &partition.name));
+        }
+
+        ranges.sort();
+        for pair in ranges.windows(2) {
+            if pair[1].0 <= pair[0].1 {
+                return Err(Error::Invalid(format!("partitions {} and {} overlap", pair[0].2, pair[1].2)));
+            }
+        }
+
+        let layout = Layout {
+            disk_guid: to_gpt_guid(&name_guid(&seed, "")),
+            entries_crc: crc32(&entries),
+            first_usable,
+            last_usable,
+        };
+        let backup_lba = disk_sectors - GPT_BACKUP_SECTORS;
+
+        let mut primary = protective_mbr(disk_sectors).to_vec();
+        primary.extend_from_slice(&layout.header(1, disk_sectors - 1, 2));
+        primary.extend_from_slice(&entries);
+        let mut backup = entries;
+        backup.extend_from_slice(&layout.header(disk_sectors - 1, 1, backup_lba));
+        Ok(Self { disk_sectors, primary, backup })
+    }
+
+    /// First sector of the backup entries.
+    pub fn backup_lba(&self) -> u64 {
+        self.disk_sectors - GPT_BACKUP_SECTORS
+    }
+
+    /// Writes both copies to a disk or disk image of `disk_sectors` sectors.
+    pub fn write_disk<W: Write + Seek>(&self, disk: &mut W) -> Result<()> {
+        disk.seek(SeekFrom::Start(0))?;
+        disk.write_all(&self.primary)?;
+        disk.seek(SeekFrom::Start(self.backup_lba() * SECTOR_SIZE))?;
+        disk.write_all(&self.backup)?;
+        Ok(())
+    }
+}
+
+/// Fields shared by the primary and backup header.
+struct Layout {
+    disk_guid: [u8; 16],
+    entries_crc: u32,
+    first_usable: u64,
+    last_usable: u64,
+}
+
+impl Layout {
+    fn header(&self, my_lba: u64, alternate_lba: u64, entries_lba: u64) -> [u8; SECTOR_SIZE as usize] {
+        let mut buf = [0u8; SECTOR_SIZE as usize];
+        buf[0..8].copy_from_slice(GPT_SIGNATURE);
+        buf[8..12].copy_from_slice(&GPT_REVISION.to_le_bytes());
+        buf[12..16].copy_from_slice(&(GPT_HEADER_LEN as u32).to_le_bytes());
+        buf[24..32].copy_from_slice(&my_lba.to_le_bytes());
+        buf[32..40].copy_from_slice(&alternate_lba.to_le_bytes());
+        buf[40..48].copy_from_slice(&self.first_usable.to_le_bytes());
+        buf[48..56].copy_from_slice(&self.last_usable.to_le_bytes());
+        buf[56..72].copy_from_slice(&self.disk_guid);
+        buf[72..80].copy_from_slice(&entries_lba.to_le_bytes());
+        buf[80..84].copy_from_slice(&(GPT_ENTRIES as u32).to_le_bytes());
+        buf[84..88].copy_from_slice(&(GPT_ENTRY_LEN as u32).to_le_bytes());
+        buf[88..92].copy_from_slice(&self.entries_crc.to_le_bytes());
+        // The header CRC is computed with its own field zeroed.
+        let crc = crc32(&buf[..GPT_HEADER_LEN]);
+        buf[16..20].copy_from_slice(&crc.to_le_bytes());
+        buf
+    }
+}
+
+/// An MBR with a single 0xEE partition covering the disk, so MBR-only tools
+/// leave it alone.
+fn protective_mbr(disk_sectors: u64) -> [u8; SECTOR_SIZE as usize] {
+    let mut mbr = [0u8; SECTOR_SIZE as usize];
+    let size = (disk_sectors - 1).min(u32::MAX as u64) as u32;
+    let entry = &mut mbr[446..462];
+    entry[1..4].copy_from_slice(&[0x00, 0x02, 0x00]);
+    entry[4] = 0xee;
+    entry[5..8].copy_from_slice(&[0xff, 0xff, 0xff]);
+    entry[8..12].copy_from_slice(&1u32.to_le_bytes());
+    entry[12..16].copy_from_slice(&size.to_le_bytes());
+    mbr[510] = 0x55;
+    mbr[511] = 0xaa;
+    mbr
+}
+
+/// Parses `xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx` into its 16 bytes in text order.
+fn parse_guid(s: &str) -> Result<[u8; 16]> {
+    let invalid = || Error::Invalid(format!("Invalid uuid '{}'", s));
+    let groups: Vec<&str> = s.split('-').collect();
+    if !s.is_ascii() || groups.iter().map(|g| g.len()).ne([8, 4, 4, 4, 12]) {
+        return Err(invalid());
+    }
+    let hex = groups.concat();
+    let mut guid = [0u8; 16];
+    for (i, byte) in guid.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).map_err(|_| invalid())?;
+    }
+    Ok(guid)
+}
+
+/// GPT stores the first three GUID fields little-endian.
+fn to_gpt_guid(guid: &[u8; 16]) -> [u8; 16] {
+    let mut out = *guid;
+    out[0..4].reverse();
+    out[4..6].reverse();
+    out[6..8].reverse();
+    out
+}
+
+/// Version 3 (MD5, name-based) GUID of `name` within the table described by `seed`.
+fn name_guid(seed: &str, name: &str) -> [u8; 16] {
+    let mut guid = md5::compute(format!("{}\0{}", seed, name)).0;
+    guid[6] = (guid[6] & 0x0f) | 0x30;
+    guid[8] = (guid[8] & 0x3f) | 0x80;
+    guid
+}
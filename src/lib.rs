@@ -1,12 +1,51 @@
-use std::{mem, str};
-use std::fs::{create_dir_all, File};
-use std::io::{Read, Seek, Write};
-use std::path::Path;
-use anyhow::{anyhow, Result};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+mod crc;
+mod error;
+mod gpt;
+mod mtdparts;
+mod observer;
+mod pack;
+mod package;
+mod parallel;
+mod parameter;
+mod path;
+mod reader;
+mod rkfw;
+mod sink;
+mod verify;
+
+pub use crc::{rkcrc32, RkCrc32};
+pub use error::{Error, Result};
+use error::PathContext;
+pub use gpt::{Gpt, GPT_BACKUP_SECTORS, GPT_PRIMARY_SECTORS};
+pub use mtdparts::{MtdPartition, PartitionTable, SECTOR_SIZE};
+pub use observer::{ImageHeader, ImageKind, Partition, UnpackObserver, Warning};
+use observer::ProgressReader;
+pub use pack::{pack_rkafp, pack_rkfw};
+pub use package::{PackageEntry, PackageFile, PackageIssue};
+use parallel::{run_ordered, ParallelTarget};
+pub use parameter::{unwrap_parameter, wrap_parameter, ParamHeader, Parameter, PARAM_HEADER_LEN};
+pub use path::{sanitize_entry_path, sanitize_relative_path, PathRejection};
+pub use reader::SubReader;
+use reader::{read_header, read_update_header, stream_len};
+pub use rkfw::{BuildDate, ChipFamily, FirmwareVersion, RkfwHeader, RKFW_HEADER_LEN};
+pub use sink::{FsSink, MemorySink, NullSink, OutputSink, TarSink};
+pub use verify::{
+    verify_file, verify_reader, verify_rkaf_crc, verify_rkfw_md5, CrcStatus, Md5Status, PackageStatus, VerifyReport,
+};
+use verify::{rkaf_crc_status, rkfw_md5_status};
 
 pub const RKAFP_MAGIC: &str = "RKAF";
 pub const PARM_MAGIC: &str = "PARM";
+/// Slots in the standard part table. Newer tools write more entries by letting
+/// the table run past the first 2 KiB of the header.
 pub const MAX_PARTS: usize = 16;
+/// Largest `num_parts` accepted when reading a header best-effort; packing stops at `MAX_PARTS`.
+pub const MAX_EXTENDED_PARTS: usize = 128;
 pub const MAX_NAME_LEN: usize = 32;
 const MAX_FULL_PATH_LEN: usize = 60;
 const MAX_MODEL_LEN: usize = 34;
@@ -14,10 +53,18 @@ const MAX_ID_LEN: usize = 30;
 const MAX_MANUFACTURER_LEN: usize = 56;
 pub const RKAF_SIGNATURE: &[u8] = b"RKAF";
 pub const RKFW_SIGNATURE: &[u8] = b"RKFW";
+/// Magic of a vendor container that is recognised but cannot be unpacked.
 pub const RKFP_SIGNATURE: &[u8] = b"RKFP";
-
-#[derive(Copy, Clone, Debug)]
-#[repr(C, packed)]
+/// On-disk size of `UpdatePart`.
+pub const UPDATE_PART_LEN: usize = 112;
+/// On-disk size of a standard `UpdateHeader`; the first part starts right after it.
+pub const UPDATE_HEADER_LEN: usize = 2048;
+/// Offset of the first part entry; `num_parts` sits just before it.
+const PART_TABLE_OFFSET: usize = 4 + 4 + MAX_MODEL_LEN + MAX_ID_LEN + MAX_MANUFACTURER_LEN + 4 + 4 + 4;
+pub(crate) const NUM_PARTS_OFFSET: usize = PART_TABLE_OFFSET - 4;
+const RESERVED_LEN: usize = 116;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct UpdatePart {
     name: [u8; MAX_NAME_LEN],
     pub full_path: [u8; MAX_FULL_PATH_LEN],
@@ -28,8 +75,7 @@ pub struct UpdatePart {
     pub part_byte_count: u32,
 }
 
-#[derive(Copy, Clone, Debug)]
-#[repr(C, packed)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct UpdateHeader {
     pub magic: [u8; 4],
     pub length: u32,
@@ -39,20 +85,15 @@ pub struct UpdateHeader {
     unknown1: u32,
     version: u32,
     pub num_parts: u32,
-    pub parts: [UpdatePart; MAX_PARTS],
-    reserved: [u8; 116],
-}
-
-#[derive(Copy, Clone, Debug)]
-#[repr(C, packed)]
-pub struct ParamHeader {
-    magic: [u8; 4],
-    length: u32,
+    /// Every slot of the part table: `MAX_PARTS` for a standard header, `num_parts`
+    /// for an extended one.
+    pub parts: Vec<UpdatePart>,
+    reserved: [u8; RESERVED_LEN],
 }
 
 
-impl UpdateHeader {
-    pub fn default() -> Self {
+impl Default for UpdateHeader {
+    fn default() -> Self {
         Self {
             magic: [0u8; 4],
             length: 0,
@@ -62,22 +103,204 @@ impl UpdateHeader {
             unknown1: 0,
             version: 0,
             num_parts: 0,
-            parts: [UpdatePart::default(); MAX_PARTS],
-            reserved: [0u8; 116],
+            parts: vec![UpdatePart::default(); MAX_PARTS],
+            reserved: [0u8; RESERVED_LEN],
+        }
+    }
+}
+
+impl UpdateHeader {
+    /// On-disk size of a header declaring `num_parts` partitions, failing if the
+    /// count is above `MAX_EXTENDED_PARTS`. Sizes past `MAX_PARTS` are a best-effort guess.
+    pub fn encoded_len(num_parts: u32) -> Result<usize> {
+        if num_parts as usize > MAX_EXTENDED_PARTS {
+            return Err(Error::InvalidPartCount {
+                count: num_parts,
+                reason: format!("at most {} are supported", MAX_EXTENDED_PARTS),
+            });
+        }
+        Ok(header_len((num_parts as usize).max(MAX_PARTS)))
+    }
+
+    /// Decodes a little-endian RKAF header, failing if `bytes` is shorter than
+    /// `encoded_len(num_parts)`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<UpdateHeader> {
+        if bytes.len() < UPDATE_HEADER_LEN {
+            return Err(Error::Truncated {
+                what: "RKAF header".to_string(),
+                offset: 0,
+                needed: UPDATE_HEADER_LEN as u64,
+                available: bytes.len() as u64,
+            });
+        }
+        let mut r = ByteReader::new(bytes);
+        let mut header = UpdateHeader {
+            magic: r.array(),
+            length: r.u32(),
+            model: r.array(),
+            id: r.array(),
+            manufacturer: r.array(),
+            unknown1: r.u32(),
+            version: r.u32(),
+            num_parts: r.u32(),
+            ..UpdateHeader::default()
+        };
+        let len = Self::encoded_len(header.num_parts)?;
+        if bytes.len() < len {
+            return Err(Error::Truncated {
+                what: "RKAF extended part table".to_string(),
+                offset: 0,
+                needed: len as u64,
+                available: bytes.len() as u64,
+            });
+        }
+        let slots = (header.num_parts as usize).max(MAX_PARTS);
+        header.parts = (0..slots)
+            .map(|_| UpdatePart::from_bytes(r.bytes(UPDATE_PART_LEN)))
+            .collect::<Result<_>>()?;
+        header.reserved = r.array();
+        Ok(header)
+    }
 
+    /// Encodes the header with all of `parts`, padded to a multiple of 2 KiB.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = vec![0u8; header_len(self.parts.len())];
+        let mut w = ByteWriter::new(&mut buf);
+        w.bytes(&self.magic);
+        w.u32(self.length);
+        w.bytes(&self.model);
+        w.bytes(&self.id);
+        w.bytes(&self.manufacturer);
+        w.u32(self.unknown1);
+        w.u32(self.version);
+        w.u32(self.num_parts);
+        for part in &self.parts {
+            w.bytes(&part.to_bytes());
         }
+        w.bytes(&self.reserved);
+        buf
     }
-    pub fn from_bytes(bytes: &[u8]) -> &UpdateHeader {
-        unsafe { mem::transmute(bytes.as_ptr()) }
+
+    /// Manufacturer up to the first NUL, or `unknown` if the field is not terminated.
+    pub fn manufacturer_name(&self) -> String {
+        cstr_or_unknown(&self.manufacturer)
     }
 
-    pub fn to_bytes(&self) -> &[u8] {
-        unsafe { std::slice::from_raw_parts(self as *const _ as *const u8, mem::size_of::<UpdateHeader>()) }
+    /// Model up to the first NUL, or `unknown` if the field is not terminated.
+    pub fn model_name(&self) -> String {
+        cstr_or_unknown(&self.model)
     }
 }
 
+/// Size of a header whose part table has `slots` entries.
+fn header_len(slots: usize) -> usize {
+    (PART_TABLE_OFFSET + slots * UPDATE_PART_LEN + RESERVED_LEN).div_ceil(UPDATE_HEADER_LEN) * UPDATE_HEADER_LEN
+}
+
+// 安全地从null-terminated字符串中提取文本
+fn cstr_or_unknown(bytes: &[u8]) -> String {
+    std::ffi::CStr::from_bytes_until_nul(bytes)
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| "unknown".into())
+}
+
 impl UpdatePart {
-    pub fn default() -> Self {
+    /// Name up to the first NUL, e.g. `boot`; matches the `package-file` and `mtdparts` names.
+    pub fn name(&self) -> String {
+        cstr_or_unknown(&self.name)
+    }
+
+    /// Full path up to the first NUL, e.g. `Image/boot.img`, as listed in `package-file`.
+    pub fn path(&self) -> String {
+        cstr_or_unknown(&self.full_path)
+    }
+
+    /// Decodes a little-endian part entry, failing if `bytes` is shorter than `UPDATE_PART_LEN`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<UpdatePart> {
+        if bytes.len() < UPDATE_PART_LEN {
+            return Err(Error::Truncated {
+                what: "RKAF part entry".to_string(),
+                offset: 0,
+                needed: UPDATE_PART_LEN as u64,
+                available: bytes.len() as u64,
+            });
+        }
+        let mut r = ByteReader::new(bytes);
+        Ok(UpdatePart {
+            name: r.array(),
+            full_path: r.array(),
+            flash_size: r.u32(),
+            part_offset: r.u32(),
+            flash_offset: r.u32(),
+            padded_size: r.u32(),
+            part_byte_count: r.u32(),
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; UPDATE_PART_LEN] {
+        let mut buf = [0u8; UPDATE_PART_LEN];
+        let mut w = ByteWriter::new(&mut buf);
+        w.bytes(&self.name);
+        w.bytes(&self.full_path);
+        w.u32(self.flash_size);
+        w.u32(self.part_offset);
+        w.u32(self.flash_offset);
+        w.u32(self.padded_size);
+        w.u32(self.part_byte_count);
+        buf
+    }
+}
+
+/// Sequential little-endian reads; callers check the total length up front.
+struct ByteReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    fn bytes(&mut self, len: usize) -> &'a [u8] {
+        let bytes = &self.buf[self.pos..self.pos + len];
+        self.pos += len;
+        bytes
+    }
+
+    fn array<const N: usize>(&mut self) -> [u8; N] {
+        let mut array = [0u8; N];
+        array.copy_from_slice(self.bytes(N));
+        array
+    }
+
+    fn u32(&mut self) -> u32 {
+        u32::from_le_bytes(self.array())
+    }
+}
+
+struct ByteWriter<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl<'a> ByteWriter<'a> {
+    fn new(buf: &'a mut [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.buf[self.pos..self.pos + bytes.len()].copy_from_slice(bytes);
+        self.pos += bytes.len();
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes(&value.to_le_bytes());
+    }
+}
+
+impl Default for UpdatePart {
+    fn default() -> Self {
         Self {
             name: [0u8; MAX_NAME_LEN],
             full_path: [0u8; MAX_FULL_PATH_LEN],
@@ -90,210 +313,381 @@ impl UpdatePart {
     }
 }
 
-pub fn unpack_file(file_path: &str, dst_path: &str) -> Result<()> {
-    let mut file = File::open(file_path)?;
-    let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer)?;
-
-    let signature = &buffer[0..4];
-    match signature {
-        RKAF_SIGNATURE => unpack_rkafp(file_path, dst_path)?,
-        RKFW_SIGNATURE => unpack_rkfw(&buffer, dst_path)?,
-        _ => {
-            return Err(anyhow!("Unknown signature: {:?}", signature));
-        }
-    }
-    Ok(())
+#[derive(Clone, Debug, Default)]
+pub struct UnpackOptions {
+    /// Refuse to extract images whose CRC or MD5 trailer is missing or wrong, or
+    /// that name a partition with an unsafe path. Without it the trailer is only
+    /// checked if the observer asks, see `UnpackObserver::wants_checksums`.
+    pub strict: bool,
+    /// Set the modification time of extracted files to the RKFW build date.
+    pub set_mtime: bool,
+    /// Unpack the RKAF image embedded in an RKFW into the same output instead of
+    /// writing it out as `embedded-update.img`.
+    pub recursive: bool,
+    /// In recursive mode, also write `embedded-update.img`.
+    pub keep_embedded: bool,
+    /// Skip partitions whose offsets are out of range or whose data is damaged,
+    /// with a warning, and extract the rest.
+    pub salvage: bool,
+    /// Number of RKAF partitions `unpack_file_with_options` and `unpack_file_to_dir`
+    /// extract at once; 0 and 1 extract them one after another. Files and progress
+    /// reports keep the partition order either way, but with several jobs
+    /// `bytes_written` is only reported once a partition is complete.
+    pub jobs: usize,
+    /// Write zero-filled blocks out in full. By default files written to a
+    /// directory on Linux are sparse, see `FsSink::with_sparse`.
+    pub no_sparse: bool,
 }
 
-fn unpack_rkfw(buf: &[u8], dst_path: &str) -> Result<()> {
-    let mut chip: Option<&str> = None;
-
-    println!("RKFW signature detected");
-    println!(
-        "version: {}.{}.{}",
-        buf[9],
-        buf[8],
-        ((buf[7] as u16) << 8) + buf[6] as u16
-    );
-    // println!(
-    //     "date: {}-{:02}-{:02} {:02}:{:02}:{:02}",
-    //     (buf[0x0f] as u16) << 8 + buf[0x0e] as u16,
-    //     buf[0x10],
-    //     buf[0x11],
-    //     buf[0x12],
-    //     buf[0x13],
-    //     buf[0x14]
-    // );
-
-    match buf[0x15] {
-        0x50 => chip = Some("rk29xx"),
-        0x60 => chip = Some("rk30xx"),
-        0x70 => chip = Some("rk31xx"),
-        0x80 => chip = Some("rk32xx"),
-        0x41 => chip = Some("rk3368"),
-        0x36 => chip = Some("RK3326"),
-        0x38 => chip = Some("RK3566"),
-        0x30 => chip = Some("PX30"),
-        _ => println!(
-            "You got a brand new chip ({:#x}), congratulations!!!",
-            buf[0x15]
-        ),
-    }
+pub fn unpack_file(file_path: &str, dst_path: &str) -> Result<()> {
+    unpack_file_with_options(file_path, dst_path, &UnpackOptions::default())
+}
 
-    println!("family: {}", chip.unwrap_or("unknown"));
-
-    let ioff = get_u32_le(&buf[0x19..]);
-    let isize: u32 = get_u32_le(&buf[0x1d..]);
-
-    // if &buf[ioff as usize..ioff as usize + 4] != b"BOOT" {
-    //     panic!("cannot find BOOT signature");
-    // }
-
-    println!(
-        "{:08x}-{:08x} {:26} (size: {})",
-        ioff,
-        ioff + isize - 1,
-        "BOOT",
-        isize
-    );
-    create_dir_all(dst_path)?;
-    write_file(
-        &Path::new(&format!("{}/BOOT", dst_path)),
-        &buf[ioff as usize..ioff as usize + (isize as usize)],
-    )?;
-
-    let ioff = get_u32_le(&buf[0x21..]);
-    let isize = get_u32_le(&buf[0x25..]);
-
-    if &buf[ioff as usize..ioff as usize + 4] != b"RKAF" {
-        panic!("cannot find embedded RKAF update.img");
-    }
+pub fn unpack_file_with_options(file_path: &str, dst_path: &str, options: &UnpackOptions) -> Result<()> {
+    let file = File::open(file_path).with_path(file_path)?;
+    unpack_file_to_dir(&file, dst_path, &mut (), options)
+}
 
-    println!(
-        "{:08x}-{:08x} {:26} (size: {})",
-        ioff,
-        ioff + isize - 1,
-        "embedded-update.img",
-        isize
-    );
-    write_file(
-        &Path::new(&format!("{}/embedded-update.img", dst_path)),
-        &buf[ioff as usize..ioff as usize + isize as usize],
-    )?;
-    Ok(())
+/// Unpacks an image file into a directory, reporting progress to `observer`.
+/// Unlike `unpack_to_sink`, this honours `UnpackOptions::jobs`.
+pub fn unpack_file_to_dir<O>(file: &File, dst_path: impl Into<PathBuf>, observer: &mut O, options: &UnpackOptions) -> Result<()>
+where
+    O: UnpackObserver + ?Sized,
+{
+    let mut sink = dir_sink(dst_path, options);
+    let parallel = ParallelTarget { file, base: 0, sink: sink.clone() };
+    let mut reader = file;
+    unpack_with(&mut reader, &mut sink, observer, options, Some(parallel))
 }
 
-pub unsafe fn any_as_u8_slice<T: Sized>(p: &T) -> &[u8] {
-    core::slice::from_raw_parts(
-        (p as *const T) as *const u8,
-        mem::size_of::<T>(),
-    )
+/// Unpacks an RKFW or RKAF image from any seekable source, e.g. an in-memory
+/// buffer or a `SubReader` over part of a larger dump.
+pub fn unpack_reader<R: Read + Seek>(reader: &mut R, dst_path: &str, options: &UnpackOptions) -> Result<()> {
+    unpack_to_sink(reader, &mut dir_sink(dst_path, options), &mut (), options)
 }
 
-pub fn info_and_fatal(is_fatal: bool, message: String) {
-    if is_fatal {
-        eprint!("rkunpack: fatal: ");
-    } else {
-        eprint!("rkunpack: info: ");
+fn dir_sink(dst_path: impl Into<PathBuf>, options: &UnpackOptions) -> FsSink {
+    let sink = FsSink::new(dst_path);
+    if options.no_sparse {
+        return sink.with_sparse(false);
     }
-    eprintln!("{}", message);
-    if is_fatal {
-        std::process::exit(1);
+    sink
+}
+
+/// Unpacks an RKFW or RKAF image into `sink` instead of a directory, reporting
+/// progress to `observer`. Pass `&mut ()` to unpack silently. Partitions are
+/// always extracted one after another.
+pub fn unpack_to_sink<R, S, O>(reader: &mut R, sink: &mut S, observer: &mut O, options: &UnpackOptions) -> Result<()>
+where
+    R: Read + Seek,
+    S: OutputSink + ?Sized,
+    O: UnpackObserver + ?Sized,
+{
+    unpack_with(reader, sink, observer, options, None)
+}
+
+fn unpack_with<R, S, O>(
+    reader: &mut R,
+    sink: &mut S,
+    observer: &mut O,
+    options: &UnpackOptions,
+    parallel: Option<ParallelTarget<'_>>,
+) -> Result<()>
+where
+    R: Read + Seek,
+    S: OutputSink + ?Sized,
+    O: UnpackObserver + ?Sized,
+{
+    let filesize = stream_len(reader)?;
+    let mut signature = [0u8; 4];
+    read_header(reader, &mut signature, filesize, "image signature")?;
+
+    let mut unpacker = Unpacker { fp: reader, filesize, sink, observer, options, mtime: None, parallel };
+    match &signature[..] {
+        RKAF_SIGNATURE => unpacker.unpack_rkafp()?,
+        RKFW_SIGNATURE => unpacker.unpack_rkfw()?,
+        RKFP_SIGNATURE => return Err(rkfp_unsupported()),
+        _ => {
+            return Err(Error::UnknownSignature { signature: signature.to_vec() });
+        }
     }
+    Ok(())
 }
-#[macro_export]
-macro_rules! info {
-    ($($arg:tt)*) => {
-        info_and_fatal(false, format!($($arg)*));
-    };
+
+/// No RKFP layout description or sample image is available, so RKFP images
+/// are refused rather than read with a guessed layout.
+pub(crate) fn rkfp_unsupported() -> Error {
+    Error::Unsupported { format: "RKFP", reason: "its header layout is not known" }
 }
 
-#[macro_export]
-macro_rules! fatal {
-    ($($arg:tt)*) => {
-        info_and_fatal(true, format!($($arg)*));
-    };
+/// State shared by the steps of one unpack run.
+struct Unpacker<'a, R, S: ?Sized, O: ?Sized> {
+    fp: &'a mut R,
+    filesize: u64,
+    sink: &'a mut S,
+    observer: &'a mut O,
+    options: &'a UnpackOptions,
+    /// Modification time for files of a nested image, taken from the outer RKFW.
+    mtime: Option<SystemTime>,
+    /// Set when the input is a file and the output a directory, so RKAF partitions
+    /// can be extracted by several threads.
+    parallel: Option<ParallelTarget<'a>>,
 }
-fn extract_file(fp: &mut File, offset: u64, len: u64, full_path: &str) -> Result<()> {
-    println!("{:08x}-{:08x} {}", offset, len, full_path);
-    let mut buffer = vec![0u8; 16 * 1024];
-    let mut fp_out = File::create(full_path)?;
 
-    fp.seek(std::io::SeekFrom::Start(offset))?;
+impl<R: Read + Seek, S: OutputSink + ?Sized, O: UnpackObserver + ?Sized> Unpacker<'_, R, S, O> {
+    fn unpack_rkfw(&mut self) -> Result<()> {
+        let mut buf = [0u8; RKFW_HEADER_LEN];
+        read_header(self.fp, &mut buf, self.filesize, "RKFW header")?;
+        let header = RkfwHeader::from_bytes(&buf)?;
+
+        let md5 = if self.checks_checksums() { Some(rkfw_md5_status(self.fp, self.filesize)?) } else { None };
+        self.observer.header_parsed(&ImageHeader::Rkfw {
+            header: &header,
+            family: header.chip_family(),
+            md5: md5.as_ref(),
+        });
+        match md5 {
+            None | Some(Md5Status::Valid(_)) => {}
+            Some(Md5Status::Mismatch { expected, actual }) => self.checksum_problem(
+                Warning::Md5Mismatch { expected: expected.clone(), actual: actual.clone() },
+                Error::ChecksumMismatch { what: "RKFW MD5", expected, actual },
+            )?,
+            Some(Md5Status::Absent) => {
+                self.checksum_problem(Warning::Md5Missing, Error::ChecksumMissing { what: "RKFW MD5" })?
+            }
+        }
+        let mtime = if self.options.set_mtime { header.date.to_system_time() } else { None };
+
+        let ioff = header.loader_offset as u64;
+        let isize = header.loader_size as u64;
+        let result = self.copy_range(ImageKind::Rkfw, ioff, isize, "BOOT", mtime);
+        self.salvage("BOOT", result)?;
+
+        let ioff = header.update_offset as u64;
+        let isize = header.update_size as u64;
+        let result = self.check_embedded_rkaf(ioff, isize);
+        if result.is_err() {
+            return self.salvage("embedded-update.img", result);
+        }
 
-    let mut remaining = len;
+        if !self.options.recursive || self.options.keep_embedded {
+            self.copy_range(ImageKind::Rkfw, ioff, isize, "embedded-update.img", mtime)?;
+        }
+        if self.options.recursive {
+            let mut image = SubReader::new(&mut *self.fp, ioff, isize);
+            let mut nested = Unpacker {
+                fp: &mut image,
+                filesize: isize,
+                sink: &mut *self.sink,
+                observer: &mut *self.observer,
+                options: self.options,
+                mtime,
+                parallel: self.parallel.as_ref().map(|parallel| parallel.nested(ioff)),
+            };
+            nested.unpack_rkafp()?;
+        }
+        Ok(())
+    }
 
-    while remaining > 0 {
-        let read_len = std::cmp::min(remaining as usize, buffer.len());
-        let read_bytes = fp.read(&mut buffer[..read_len])?;
+    fn unpack_rkafp(&mut self) -> Result<()> {
+        let header = read_update_header(self.fp, self.filesize)?;
+
+        let crc = if self.checks_checksums() {
+            Some(rkaf_crc_status(self.fp, header.length as u64, self.filesize)?)
+        } else {
+            None
+        };
+        self.observer.header_parsed(&ImageHeader::Rkaf {
+            header: &header,
+            filesize: self.filesize,
+            crc: crc.as_ref(),
+        });
+        match crc {
+            None | Some(CrcStatus::Valid(_)) => {}
+            Some(CrcStatus::Mismatch { expected, actual }) => self.checksum_problem(
+                Warning::CrcMismatch { expected, actual },
+                Error::ChecksumMismatch {
+                    what: "RKAF CRC",
+                    expected: format!("{:08x}", expected),
+                    actual: format!("{:08x}", actual),
+                },
+            )?,
+            Some(CrcStatus::Absent) => {
+                self.checksum_problem(Warning::CrcMissing, Error::ChecksumMissing { what: "RKAF CRC" })?
+            }
+        }
 
-        if read_bytes != read_len {
-            return Err(anyhow!("Insufficient length in container image file"));
+        let mut entries = Vec::new();
+        for part in &header.parts[..header.num_parts as usize] {
+            // 路径来自不可信的映像，写出前必须检查
+            let part_full_path = match sanitize_entry_path(&part.full_path) {
+                Ok(path) => path,
+                Err(reason) => {
+                    let len = part.full_path.iter().position(|&b| b == 0).unwrap_or(part.full_path.len());
+                    let path = String::from_utf8_lossy(&part.full_path[..len]).into_owned();
+                    self.unsafe_path(path, reason)?;
+                    continue;
+                }
+            };
+            if part_full_path == "SELF" || part_full_path == "RESERVED" {
+                continue;
+            }
+            entries.push((part_full_path, part.part_offset as u64, part.part_byte_count as u64));
         }
 
-        fp_out.write_all(&buffer[..read_len])?;
+        match self.parallel.clone() {
+            Some(parallel) if self.options.jobs > 1 => self.copy_parallel(&parallel, entries),
+            _ => {
+                for (path, offset, len) in entries {
+                    let result = self.copy_range(ImageKind::Rkaf, offset, len, &path, self.mtime);
+                    self.salvage(&path, result)?;
+                }
+                Ok(())
+            }
+        }
+    }
 
-        remaining -= read_len as u64;
+    /// Extracts RKAF partitions on `options.jobs` threads, reporting them in order.
+    fn copy_parallel(&mut self, parallel: &ParallelTarget<'_>, entries: Vec<(String, u64, u64)>) -> Result<()> {
+        let mut checks = Vec::new();
+        let mut partitions = Vec::new();
+        for (path, offset, len) in entries {
+            checks.push(self.check_range(&path, offset, len));
+            partitions.push(Partition {
+                image: ImageKind::Rkaf,
+                display_path: self.sink.display_path(&path),
+                path,
+                offset,
+                len,
+            });
+        }
+        let in_range: Vec<bool> = checks.iter().map(Result::is_ok).collect();
+        let mut checks = checks.into_iter();
+        let mtime = self.mtime;
+
+        run_ordered(
+            &partitions,
+            self.options.jobs,
+            |i, partition| {
+                if !in_range[i] {
+                    return Ok(());
+                }
+                parallel.copy(partition.offset, partition.len, &partition.path, mtime)
+            },
+            |i, result| {
+                let partition = &partitions[i];
+                let result = checks.next().unwrap().and_then(|()| {
+                    self.observer.partition_started(partition);
+                    result?;
+                    self.observer.bytes_written(partition, partition.len);
+                    self.observer.partition_finished(partition);
+                    Ok(())
+                });
+                self.salvage(&partition.path, result)
+            },
+        )
     }
 
-    Ok(())
-}
+    /// Checksums cost a full extra read, so they are only checked when something uses the result.
+    fn checks_checksums(&self) -> bool {
+        self.options.strict || self.observer.wants_checksums()
+    }
 
-fn unpack_rkafp(file_path: &str, dst_path: &str) -> Result<()> {
-    let mut fp = File::open(file_path)?;
-    let mut buf = vec![0u8; mem::size_of::<UpdateHeader>()];
-    fp.read_exact(&mut buf)?;
-    let header = UpdateHeader::from_bytes(buf.as_mut());
-    let magic_str = str::from_utf8(&header.magic)?;
-    if magic_str != RKAFP_MAGIC {
-        return Err(anyhow!("Invalid header magic id"));
+    /// Reports a bad or missing checksum, failing in strict mode.
+    fn checksum_problem(&mut self, warning: Warning, error: Error) -> Result<()> {
+        self.observer.warning(&warning);
+        if self.options.strict {
+            return Err(error);
+        }
+        Ok(())
     }
 
-    let filesize = fp.metadata()?.len();
-    println!("Filesize: {}", filesize);
-    if filesize - 4 != header.length as u64 {
-        eprintln!("update_header.length cannot be correct, cannot check CRC");
+    /// Reports a partition skipped because of its path, failing in strict mode.
+    fn unsafe_path(&mut self, path: String, reason: PathRejection) -> Result<()> {
+        self.observer.warning(&Warning::UnsafePath { path: path.clone(), reason });
+        if self.options.strict {
+            return Err(Error::UnsafePath { path, reason });
+        }
+        Ok(())
     }
-    create_dir_all(format!("{}/Image", dst_path))?;
-    // 安全地从null-terminated字符串中提取文本
-    let manufacturer = std::ffi::CStr::from_bytes_until_nul(&header.manufacturer)
-        .map(|s| s.to_string_lossy())
-        .unwrap_or_else(|_| "unknown".into());
-    let model = std::ffi::CStr::from_bytes_until_nul(&header.model)
-        .map(|s| s.to_string_lossy())
-        .unwrap_or_else(|_| "unknown".into());
-    
-    println!("manufacturer: {}", manufacturer);
-    println!("model: {}", model);
-    for i in 0..header.num_parts {
-        let part = &header.parts[i as usize];
-        // 安全地提取路径字符串
-        if let Ok(cstr) = std::ffi::CStr::from_bytes_until_nul(&part.full_path) {
-            let part_full_path = cstr.to_string_lossy();
-            if part_full_path == "SELF" || part_full_path == "RESERVED" {
-                continue;
+
+    /// In salvage mode, turns a damaged partition into a warning so the rest
+    /// of the image is still extracted.
+    fn salvage(&mut self, path: &str, result: Result<()>) -> Result<()> {
+        match result {
+            Err(err @ (Error::OffsetOutOfBounds { .. } | Error::Truncated { .. } | Error::BadMagic { .. }))
+                if self.options.salvage =>
+            {
+                self.observer.warning(&Warning::PartitionSkipped {
+                    path: path.to_string(),
+                    reason: err.to_string(),
+                });
+                Ok(())
             }
-            let part_full_path = format!("{}/{}", dst_path, part_full_path);
-            extract_file(
-                &mut fp,
-                part.part_offset as u64,
-                part.part_byte_count as u64,
-                &part_full_path,
-            )?;
+            result => result,
         }
     }
 
-    Ok(())
-}
-
+    /// Checks that `len` bytes at `offset`, as declared by a header, lie inside the input.
+    fn check_range(&self, what: &str, offset: u64, len: u64) -> Result<()> {
+        let out_of_bounds = || Error::OffsetOutOfBounds {
+            what: what.to_string(),
+            offset,
+            size: len,
+            limit: self.filesize,
+        };
+        let end = offset.checked_add(len).ok_or_else(out_of_bounds)?;
+        if offset > self.filesize {
+            return Err(out_of_bounds());
+        }
+        if end > self.filesize {
+            return Err(Error::Truncated {
+                what: what.to_string(),
+                offset,
+                needed: len,
+                available: self.filesize - offset,
+            });
+        }
+        Ok(())
+    }
 
-fn get_u32_le(slice: &[u8]) -> u32 {
-    u32::from_le_bytes([slice[0], slice[1], slice[2], slice[3]])
-}
+    fn check_embedded_rkaf(&mut self, offset: u64, len: u64) -> Result<()> {
+        self.check_range("embedded-update.img", offset, len)?;
+        let mut magic = Vec::with_capacity(4);
+        self.fp.seek(SeekFrom::Start(offset))?;
+        Read::by_ref(self.fp).take(len.min(4)).read_to_end(&mut magic)?;
+        if magic != RKAF_SIGNATURE {
+            return Err(Error::BadMagic {
+                what: "embedded RKAF update.img",
+                offset,
+                expected: RKAF_SIGNATURE,
+                found: magic,
+            });
+        }
+        Ok(())
+    }
 
-fn write_file(path: &Path, buffer: &[u8]) -> Result<()> {
-    let mut file = File::create(path)?;
-    file.write_all(buffer)?;
-    Ok(())
+    /// Streams `len` bytes at `offset` of the image into the sink as `path`.
+    fn copy_range(&mut self, image: ImageKind, offset: u64, len: u64, path: &str, mtime: Option<SystemTime>) -> Result<()> {
+        self.check_range(path, offset, len)?;
+        let partition = Partition {
+            image,
+            path: path.to_string(),
+            display_path: self.sink.display_path(path),
+            offset,
+            len,
+        };
+        self.observer.partition_started(&partition);
+
+        self.fp.seek(SeekFrom::Start(offset))?;
+        let mut data = ProgressReader {
+            inner: &mut Read::by_ref(self.fp).take(len),
+            observer: &mut *self.observer,
+            partition: &partition,
+            written: 0,
+        };
+        self.sink.write_file(path, len, mtime, &mut data)?;
+        self.observer.partition_finished(&partition);
+        Ok(())
+    }
 }
@@ -1,23 +1,405 @@
-use clap::Parser;
-use afptool_rs::unpack_file;
-use anyhow::Result;
+use clap::{Args as ClapArgs, Parser, Subcommand};
+use std::fs::{self, File};
+use std::io::Read;
+use afptool_rs::{
+    pack_rkafp, pack_rkfw, unpack_file_to_dir, verify_file, BuildDate, ChipFamily, CrcStatus, Error,
+    FirmwareVersion, Gpt, ImageHeader, ImageKind, Md5Status, MtdPartition, PackageStatus, Parameter, Partition,
+    RkfwHeader, UnpackObserver, UnpackOptions, Warning, RKFW_HEADER_LEN, SECTOR_SIZE,
+};
+use anyhow::{anyhow, Result};
 
 #[derive(Parser)]
 #[command(name = "afptool-rs")]
 #[command(about = "A Rust tool for unpacking RockChip firmware images")]
 #[command(version)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Input firmware file path
-    #[arg(help = "Path to the firmware file (RKFW or RKAF format)")]
-    input: String,
-    
+    #[arg(help = "Path to the firmware file (RKFW or RKAF format)", required = true)]
+    input: Option<String>,
+
     /// Output directory path
-    #[arg(help = "Directory where extracted files will be saved")]
-    output: String,
+    #[arg(help = "Directory where extracted files will be saved", required = true)]
+    output: Option<String>,
+
+    /// Refuse to extract images whose checksum is missing or wrong
+    #[arg(long)]
+    strict: bool,
+
+    /// Skip damaged partitions and extract whatever is intact
+    #[arg(long, conflicts_with = "strict")]
+    salvage: bool,
+
+    /// Set the modification time of extracted files to the RKFW build date
+    #[arg(long)]
+    set_mtime: bool,
+
+    /// Unpack the update image inside an RKFW straight into Image/
+    #[arg(short, long)]
+    recursive: bool,
+
+    /// With --recursive, also write embedded-update.img
+    #[arg(long, requires = "recursive")]
+    keep_embedded: bool,
+
+    /// Number of partitions to extract at once
+    #[arg(short, long, default_value_t = 1)]
+    jobs: usize,
+
+    /// Write zero-filled regions out instead of leaving holes (sparse files are the default on Linux)
+    #[arg(long)]
+    no_sparse: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Pack a directory with a package-file into an RKAF update image
+    Pack {
+        #[arg(help = "Directory containing package-file and the files it lists")]
+        src_dir: String,
+
+        #[arg(help = "Path of the RKAF image to create")]
+        output: String,
+    },
+    /// Combine a loader and an RKAF update image into an RKFW firmware image
+    PackRkfw {
+        #[arg(help = "Loader binary, e.g. MiniLoaderAll.bin")]
+        loader: String,
+
+        #[arg(help = "RKAF update image")]
+        update: String,
+
+        #[arg(help = "Path of the RKFW image to create")]
+        output: String,
+
+        #[arg(long, help = "Chip family name (e.g. RK3399) or chip field value (e.g. 0x50)")]
+        chip: ChipFamily,
+
+        #[arg(long = "fw-version", default_value = "1.0.0", help = "Firmware version as major.minor.small")]
+        fw_version: FirmwareVersion,
+
+        #[arg(long, help = "Build date as \"YYYY-MM-DD HH:MM:SS\", defaults to now (UTC)")]
+        date: Option<BuildDate>,
+
+        #[arg(long, value_name = "IMAGE", help = "RKFW image to copy the unknown header bytes from, zeroed otherwise")]
+        reference: Option<String>,
+    },
+    /// Check the MD5 and CRC trailers of a firmware image without extracting it
+    Verify {
+        #[arg(help = "Path to the firmware file (RKFW or RKAF format)")]
+        input: String,
+    },
+    /// Generate a GPT from the partitions in parameter.txt
+    Gpt {
+        #[arg(help = "parameter.txt, with or without its PARM header")]
+        parameter: String,
+
+        #[arg(help = "Where to write the protective MBR, primary header and entries (gpt.img)")]
+        output: String,
+
+        #[arg(long, value_parser = parse_size, help = "Disk size in bytes, or with a K/M/G/T suffix (e.g. 32G)")]
+        disk_size: u64,
+
+        #[arg(long, help = "Also write the backup entries and header to this file")]
+        backup: Option<String>,
+
+        #[arg(long, conflicts_with = "backup", help = "Write a disk-sized image with both copies instead of gpt.img")]
+        disk_image: bool,
+    },
+    /// Edit parameter.txt and write it back in its PARM envelope
+    EditParam(EditParam),
+}
+
+#[derive(ClapArgs)]
+struct EditParam {
+    #[arg(help = "parameter.txt, with or without its PARM header")]
+    parameter: String,
+
+    #[arg(short, long, help = "Where to write the result, defaults to overwriting the input")]
+    output: Option<String>,
+
+    #[arg(long, value_name = "KEY=VALUE", help = "Set a key, e.g. MACHINE_MODEL=RK3568")]
+    set: Vec<String>,
+
+    #[arg(long, value_name = "KEY", help = "Remove a key")]
+    unset: Vec<String>,
+
+    #[arg(long = "remove-partition", value_name = "NAME", help = "Remove a partition")]
+    remove_partition: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "NAME=SIZE",
+        help = "Resize a partition and move the ones behind it; SIZE is 0x-prefixed sectors, bytes with an optional K/M/G/T suffix, or - to grow"
+    )]
+    resize: Vec<String>,
+
+    #[arg(long = "add-partition", value_name = "SIZE@START(NAME)", help = "Add a partition, e.g. 0x00100000@0x00600000(oem)")]
+    add_partition: Vec<MtdPartition>,
+
+    #[arg(long, value_name = "NAME[=VALUE]", help = "Set a kernel argument in CMDLINE, e.g. console=ttyS2 or rootwait")]
+    bootarg: Vec<String>,
+
+    #[arg(long, value_name = "NAME", help = "Remove a kernel argument from CMDLINE")]
+    remove_bootarg: Vec<String>,
+
+    #[arg(long, help = "Write plain text without the PARM envelope")]
+    plain: bool,
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
-    unpack_file(&args.input, &args.output)?;
+    match args.command {
+        Some(Command::Pack { src_dir, output }) => {
+            print_packed(&pack_rkafp(&src_dir, &output)?);
+        }
+        Some(Command::PackRkfw { loader, update, output, chip, fw_version, date, reference }) => {
+            let date = date.unwrap_or_else(BuildDate::now);
+            let reference = reference.as_deref().map(read_rkfw_header).transpose()?;
+            print_packed(&pack_rkfw(&loader, &update, chip, fw_version, date, reference.as_ref(), &output)?);
+        }
+        Some(Command::Verify { input }) => verify(&input)?,
+        Some(Command::Gpt { parameter, output, disk_size, backup, disk_image }) => {
+            gpt(&parameter, &output, disk_size, backup.as_deref(), disk_image)?
+        }
+        Some(Command::EditParam(edit)) => edit_param(edit)?,
+        None => {
+            let (Some(input), Some(output)) = (args.input, args.output) else {
+                unreachable!("clap enforces the required arguments");
+            };
+            let options = UnpackOptions {
+                strict: args.strict,
+                set_mtime: args.set_mtime,
+                recursive: args.recursive,
+                keep_embedded: args.keep_embedded,
+                salvage: args.salvage,
+                jobs: args.jobs,
+                no_sparse: args.no_sparse,
+            };
+            let file = File::open(&input).map_err(|source| Error::File { path: input.into(), source })?;
+            unpack_file_to_dir(&file, &output, &mut ConsoleObserver, &options)?;
+        }
+    }
+    Ok(())
+}
+
+/// Prints unpack progress in the format afptool has always used.
+struct ConsoleObserver;
+
+impl UnpackObserver for ConsoleObserver {
+    fn wants_checksums(&self) -> bool {
+        true
+    }
+
+    fn header_parsed(&mut self, header: &ImageHeader<'_>) {
+        match header {
+            ImageHeader::Rkfw { header, family, md5 } => {
+                println!("{} signature detected", String::from_utf8_lossy(&header.magic));
+                if let Some(Md5Status::Valid(md5)) = md5 {
+                    println!("MD5: {} OK", md5);
+                }
+                println!("version: {}", header.version);
+                if header.date.is_valid() {
+                    println!("date: {}", header.date);
+                } else {
+                    println!("date: unknown");
+                }
+                if let ChipFamily::Unknown(code) = family {
+                    println!("You got a brand new chip ({:#x}), congratulations!!!", code);
+                }
+                println!("family: {}", family.name().unwrap_or("unknown"));
+            }
+            ImageHeader::Rkaf { header, filesize, crc } => {
+                println!("Filesize: {}", filesize);
+                if let Some(CrcStatus::Valid(crc)) = crc {
+                    println!("CRC: {:08x} OK", crc);
+                }
+                println!("manufacturer: {}", header.manufacturer_name());
+                println!("model: {}", header.model_name());
+            }
+        }
+    }
+
+    fn partition_started(&mut self, partition: &Partition) {
+        print_partition(partition);
+    }
+
+    fn warning(&mut self, warning: &Warning) {
+        eprintln!("{}", warning);
+    }
+}
+
+/// Lists the parts a pack command wrote, in the same format as unpacking.
+fn print_packed(partitions: &[Partition]) {
+    partitions.iter().for_each(print_partition);
+}
+
+fn print_partition(partition: &Partition) {
+    match partition.image {
+        ImageKind::Rkfw => println!(
+            "{:08x}-{:08x} {:26} (size: {})",
+            partition.offset,
+            (partition.offset + partition.len).saturating_sub(1),
+            partition.path,
+            partition.len
+        ),
+        ImageKind::Rkaf => println!(
+            "{:08x}-{:08x} {}",
+            partition.offset, partition.len, partition.display_path
+        ),
+    }
+}
+
+fn gpt(parameter: &str, output: &str, disk_size: u64, backup: Option<&str>, disk_image: bool) -> Result<()> {
+    let data = fs::read(parameter).map_err(|source| Error::File { path: parameter.into(), source })?;
+    let table = Parameter::from_bytes(&data)?
+        .partition_table()?
+        .ok_or_else(|| anyhow!("{} has no mtdparts in CMDLINE", parameter))?;
+    let gpt = Gpt::from_table(&table, disk_size)?;
+
+    let write_error = |source| Error::File { path: output.into(), source };
+    if disk_image {
+        let mut file = File::create(output).map_err(write_error)?;
+        file.set_len(gpt.disk_sectors * SECTOR_SIZE).map_err(write_error)?;
+        gpt.write_disk(&mut file)?;
+    } else {
+        fs::write(output, &gpt.primary).map_err(write_error)?;
+    }
+    if let Some(backup) = backup {
+        fs::write(backup, &gpt.backup).map_err(|source| Error::File { path: backup.into(), source })?;
+    }
+    for partition in &table.partitions {
+        println!("{}", partition);
+    }
+    println!("backup GPT at LBA {:#x}", gpt.backup_lba());
+    Ok(())
+}
+
+/// Applies key edits, then partition edits, then kernel argument edits.
+fn edit_param(edit: EditParam) -> Result<()> {
+    let data = fs::read(&edit.parameter).map_err(|source| Error::File { path: edit.parameter.as_str().into(), source })?;
+    let mut parameter = Parameter::from_bytes(&data)?;
+
+    for assignment in &edit.set {
+        let (key, value) = assignment.split_once('=').ok_or_else(|| anyhow!("expected KEY=VALUE, got '{}'", assignment))?;
+        parameter.set(key, value)?;
+    }
+    for key in &edit.unset {
+        if !parameter.remove(key) {
+            return Err(anyhow!("{} is not set", key));
+        }
+    }
+
+    if !edit.remove_partition.is_empty() || !edit.resize.is_empty() || !edit.add_partition.is_empty() {
+        let mut table = parameter
+            .partition_table()?
+            .ok_or_else(|| anyhow!("{} has no mtdparts in CMDLINE", edit.parameter))?;
+        for name in &edit.remove_partition {
+            table.remove(name).ok_or_else(|| anyhow!("no partition named {}", name))?;
+        }
+        for resize in &edit.resize {
+            let (name, size) = resize.split_once('=').ok_or_else(|| anyhow!("expected NAME=SIZE, got '{}'", resize))?;
+            table.resize(name, parse_sectors(size)?)?;
+        }
+        for partition in &edit.add_partition {
+            table.add(partition.clone())?;
+        }
+        parameter.set_partition_table(&table);
+    }
+
+    for arg in &edit.bootarg {
+        let (name, value) = arg.split_once('=').unwrap_or((arg, ""));
+        parameter.set_bootarg(name, value)?;
+    }
+    for name in &edit.remove_bootarg {
+        if !parameter.remove_bootarg(name) {
+            return Err(anyhow!("CMDLINE has no {} argument", name));
+        }
+    }
+
+    let output = edit.output.as_deref().unwrap_or(&edit.parameter);
+    let data = if edit.plain { parameter.to_string().into_bytes() } else { parameter.to_bytes() };
+    fs::write(output, data).map_err(|source| Error::File { path: output.into(), source })?;
+    print!("{}", parameter);
+    Ok(())
+}
+
+/// Partition size for `--resize`: `-`, `0x`-prefixed sectors as in mtdparts,
+/// or a byte count that is a whole number of sectors.
+fn parse_sectors(s: &str) -> Result<Option<u64>> {
+    if s == "-" {
+        return Ok(None);
+    }
+    if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        return Ok(Some(u64::from_str_radix(hex, 16).map_err(|_| anyhow!("invalid size '{}'", s))?));
+    }
+    let bytes = parse_size(s).map_err(|e| anyhow!(e))?;
+    if bytes % SECTOR_SIZE != 0 {
+        return Err(anyhow!("size '{}' is not a multiple of {} bytes", s, SECTOR_SIZE));
+    }
+    Ok(Some(bytes / SECTOR_SIZE))
+}
+
+/// Parses a byte count with an optional binary K/M/G/T suffix.
+fn parse_size(s: &str) -> std::result::Result<u64, String> {
+    let s = s.trim();
+    let (digits, shift) = match s.char_indices().last() {
+        Some((i, 'K' | 'k')) => (&s[..i], 10),
+        Some((i, 'M' | 'm')) => (&s[..i], 20),
+        Some((i, 'G' | 'g')) => (&s[..i], 30),
+        Some((i, 'T' | 't')) => (&s[..i], 40),
+        _ => (s, 0),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(1 << shift))
+        .ok_or_else(|| format!("invalid size '{}'", s))
+}
+
+/// Reads the header of an existing RKFW image.
+fn read_rkfw_header(path: &str) -> Result<RkfwHeader> {
+    let mut buf = [0u8; RKFW_HEADER_LEN];
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut buf))
+        .map_err(|source| Error::File { path: path.into(), source })?;
+    Ok(RkfwHeader::from_bytes(&buf)?)
+}
+
+fn verify(input: &str) -> Result<()> {
+    let report = verify_file(input)?;
+    match &report.md5 {
+        Some(Md5Status::Valid(md5)) => println!("MD5: {} OK", md5),
+        Some(Md5Status::Mismatch { expected, actual }) => {
+            println!("MD5: mismatch, image says {}, computed {}", expected, actual)
+        }
+        Some(Md5Status::Absent) => println!("MD5: trailer missing"),
+        None => {}
+    }
+    match &report.crc {
+        Some(CrcStatus::Valid(crc)) => println!("CRC: {:08x} OK", crc),
+        Some(CrcStatus::Mismatch { expected, actual }) => {
+            println!("CRC: mismatch, image says {:08x}, computed {:08x}", expected, actual)
+        }
+        Some(CrcStatus::Absent) => println!("CRC: trailer missing"),
+        None => {}
+    }
+    match &report.package {
+        Some(PackageStatus::Valid(entries)) => println!("package-file: {} entries OK", entries),
+        Some(PackageStatus::Mismatch(issues)) => {
+            for issue in issues {
+                println!("package-file: {}", issue);
+            }
+        }
+        Some(PackageStatus::Invalid(reason)) => println!("package-file: {}", reason),
+        Some(PackageStatus::Absent) => println!("package-file: missing"),
+        None => {}
+    }
+    if !report.is_ok() {
+        return Err(anyhow!("{} failed verification", input));
+    }
     Ok(())
-}
\ No newline at end of file
+}
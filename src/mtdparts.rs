@@ -0,0 +1,215 @@
+use std::fmt;
+use std::str::FromStr;
+use crate::{Error, Result, UpdateHeader, UpdatePart};
+
+/// Size of the sectors `mtdparts` offsets and sizes are counted in.
+pub const SECTOR_SIZE: u64 = 512;
+
+/// One entry of an `mtdparts` list, e.g. `0x00002000@0x00004000(uboot)`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MtdPartition {
+    pub name: String,
+    /// First sector (LBA).
+    pub start: u64,
+    /// Size in sectors, or `None` for `-`: up to the end of the device.
+    pub size: Option<u64>,
+    /// The `:grow` flag, set on the last partition so it fills the device.
+    pub grow: bool,
+    /// From a `uuid:<name>=<uuid>` line of `parameter.txt`; not part of `mtdparts`.
+    pub uuid: Option<String>,
+}
+
+impl MtdPartition {
+    /// The part of an RKAF image with the same name, e.g. the `boot` part for the
+    /// `boot` partition.
+    pub fn update_part<'a>(&self, header: &'a UpdateHeader) -> Option<&'a UpdatePart> {
+        let num_parts = (header.num_parts as usize).min(header.parts.len());
+        header.parts[..num_parts].iter().find(|part| part.name() == self.name)
+    }
+
+    /// First sector after the partition, `None` if it runs to the end of the device.
+    pub fn end(&self) -> Option<u64> {
+        self.size.map(|size| self.start.saturating_add(size))
+    }
+}
+
+impl fmt::Display for MtdPartition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.size {
+            Some(size) => write!(f, "{:#010x}", size)?,
+            None => f.write_str("-")?,
+        }
+        write!(f, "@{:#010x}({}", self.start, self.name)?;
+        if self.grow {
+            f.write_str(":grow")?;
+        }
+        f.write_str(")")
+    }
+}
+
+impl FromStr for MtdPartition {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = |what: &str| Error::Invalid(format!("Invalid mtdparts entry '{}': {}", s, what));
+        let (geometry, rest) = s.trim().split_once('(').ok_or_else(|| invalid("expected size@start(name)"))?;
+        let label = rest.strip_suffix(')').ok_or_else(|| invalid("missing ')'"))?;
+        let (name, grow) = match label.split_once(':') {
+            Some((name, "grow")) => (name, true),
+            Some((_, flag)) => return Err(invalid(&format!("unknown flag '{}'", flag))),
+            None => (label, false),
+        };
+        if name.is_empty() {
+            return Err(invalid("empty name"));
+        }
+        let (size, start) = geometry.split_once('@').ok_or_else(|| invalid("missing '@start'"))?;
+        let size = match size {
+            "-" => None,
+            size => Some(parse_sectors(size).ok_or_else(|| invalid("invalid size"))?),
+        };
+        Ok(Self {
+            name: name.to_string(),
+            start: parse_sectors(start).ok_or_else(|| invalid("invalid start"))?,
+            size,
+            grow,
+            uuid: None,
+        })
+    }
+}
+
+/// The partition layout from `mtdparts=<device>:<partition>,...` in the
+/// `CMDLINE` of `parameter.txt`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PartitionTable {
+    /// Device id, `rk29xxnand` in all Rockchip images.
+    pub device: String,
+    pub partitions: Vec<MtdPartition>,
+}
+
+impl PartitionTable {
+    /// Finds the `mtdparts=` option in a kernel command line and parses it.
+    pub fn from_cmdline(cmdline: &str) -> Result<Self> {
+        cmdline
+            .split_whitespace()
+            .find(|option| option.starts_with("mtdparts="))
+            .ok_or_else(|| Error::Invalid("CMDLINE has no mtdparts= option".to_string()))?
+            .parse()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&MtdPartition> {
+        self.partitions.iter().find(|partition| partition.name == name)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut MtdPartition> {
+        self.partitions.iter_mut().find(|partition| partition.name == name)
+    }
+
+    /// Inserts `partition` before the first partition that starts after it.
+    /// Fails if the name is taken or the sectors overlap another partition.
+    pub fn add(&mut self, partition: MtdPartition) -> Result<()> {
+        if self.get(&partition.name).is_some() {
+            return Err(Error::Invalid(format!("partition {} already exists", partition.name)));
+        }
+        let overlaps = |other: &MtdPartition| {
+            partition.end().is_none_or(|end| other.start < end)
+                && other.end().is_none_or(|end| partition.start < end)
+        };
+        if let Some(other) = self.partitions.iter().find(|other| overlaps(other)) {
+            return Err(Error::Invalid(format!("partition {} overlaps {}", partition.name, other.name)));
+        }
+        let index = self
+            .partitions
+            .iter()
+            .position(|other| other.start > partition.start)
+            .unwrap_or(self.partitions.len());
+        self.partitions.insert(index, partition);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<MtdPartition> {
+        let index = self.partitions.iter().position(|partition| partition.name == name)?;
+        Some(self.partitions.remove(index))
+    }
+
+    /// Changes the size of a partition and moves the partitions behind it by
+    /// the same amount, so the layout stays packed. `None` (`-`) makes it a
+    /// `grow` partition, which only the last partition can be.
+    pub fn resize(&mut self, name: &str, size: Option<u64>) -> Result<()> {
+        let invalid = |what: &str| Error::Invalid(format!("Cannot resize partition {}: {}", name, what));
+        let index = self
+            .partitions
+            .iter()
+            .position(|partition| partition.name == name)
+            .ok_or_else(|| invalid("no such partition"))?;
+        let partition = &self.partitions[index];
+        let start = partition.start;
+        let new_end = match size {
+            Some(size) => Some(start.checked_add(size).ok_or_else(|| invalid("size too large"))?),
+            None => None,
+        };
+        match (partition.end(), new_end) {
+            (Some(old_end), Some(new_end)) => {
+                let starts = self
+                    .partitions
+                    .iter()
+                    .map(|other| match other.start >= old_end {
+                        true => (other.start - old_end)
+                            .checked_add(new_end)
+                            .ok_or_else(|| invalid(&format!("{} would move past the last sector", other.name))),
+                        false => Ok(other.start),
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                for (other, start) in self.partitions.iter_mut().zip(starts) {
+                    other.start = start;
+                }
+            }
+            _ => {
+                if let Some(other) = self.partitions.iter().find(|other| other.start > start) {
+                    return Err(invalid(&format!("{} follows it, only the last partition can grow", other.name)));
+                }
+            }
+        }
+        let partition = &mut self.partitions[index];
+        partition.size = size;
+        partition.grow = size.is_none();
+        Ok(())
+    }
+}
+
+impl fmt::Display for PartitionTable {
+    /// Writes the `mtdparts=` option; uuids are not part of it.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "mtdparts={}:", self.device)?;
+        for (i, partition) in self.partitions.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{}", partition)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for PartitionTable {
+    type Err = Error;
+
+    /// Accepts `mtdparts=<device>:<list>` or just `<device>:<list>`.
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let spec = s.strip_prefix("mtdparts=").unwrap_or(s);
+        let (device, list) = spec
+            .split_once(':')
+            .ok_or_else(|| Error::Invalid(format!("Invalid mtdparts '{}': expected <device>:<partitions>", s)))?;
+        let partitions = match list {
+            "" => Vec::new(),
+            list => list.split(',').map(str::parse).collect::<Result<_>>()?,
+        };
+        Ok(Self { device: device.to_string(), partitions })
+    }
+}
+
+/// Sector counts are written as `0x`-prefixed hexadecimal.
+fn parse_sectors(value: &str) -> Option<u64> {
+    let digits = value.strip_prefix("0x").or_else(|| value.strip_prefix("0X"))?;
+    u64::from_str_radix(digits, 16).ok()
+}
@@ -0,0 +1,137 @@
+use std::fmt;
+use std::io::{self, Read};
+use crate::{ChipFamily, CrcStatus, Md5Status, PathRejection, RkfwHeader, UpdateHeader};
+
+/// Container format an extracted file comes from.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ImageKind {
+    Rkfw,
+    Rkaf,
+}
+
+/// A parsed image header, reported before anything is extracted from it.
+/// The checksum is `None` when it was not checked, see `UnpackObserver::wants_checksums`.
+#[derive(Clone, Debug)]
+pub enum ImageHeader<'a> {
+    Rkfw {
+        header: &'a RkfwHeader,
+        family: ChipFamily,
+        md5: Option<&'a Md5Status>,
+    },
+    Rkaf {
+        header: &'a UpdateHeader,
+        filesize: u64,
+        crc: Option<&'a CrcStatus>,
+    },
+}
+
+/// A file being extracted: `len` bytes at `offset` of the image.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Partition {
+    pub image: ImageKind,
+    /// Path relative to the output root.
+    pub path: String,
+    /// Path as the sink wants it shown, e.g. including the output directory.
+    pub display_path: String,
+    pub offset: u64,
+    pub len: u64,
+}
+
+/// Problems that do not stop unpacking unless `UnpackOptions::strict` is set.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Warning {
+    Md5Mismatch { expected: String, actual: String },
+    Md5Missing,
+    CrcMismatch { expected: u32, actual: u32 },
+    CrcMissing,
+    /// A partition whose `full_path` is unsafe was skipped.
+    UnsafePath { path: String, reason: PathRejection },
+    /// A damaged partition was skipped in salvage mode.
+    PartitionSkipped { path: String, reason: String },
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warning::Md5Mismatch { expected, actual } => {
+                write!(f, "MD5 mismatch: image says {}, computed {}", expected, actual)
+            }
+            Warning::Md5Missing => write!(f, "no MD5 trailer found, cannot check MD5"),
+            Warning::CrcMismatch { expected, actual } => {
+                write!(f, "CRC mismatch: image says {:08x}, computed {:08x}", expected, actual)
+            }
+            Warning::CrcMissing => write!(f, "update_header.length cannot be correct, cannot check CRC"),
+            Warning::UnsafePath { path, reason } => write!(f, "skipping {:?}: {}", path, reason),
+            Warning::PartitionSkipped { path, reason } => write!(f, "skipping {}: {}", path, reason),
+        }
+    }
+}
+
+/// Receives progress while an image is unpacked. Every method defaults to doing nothing;
+/// `()` is the silent observer.
+pub trait UnpackObserver {
+    /// Whether to check the MD5 or CRC trailer, and warn about it, without
+    /// `UnpackOptions::strict`. Checking reads the whole image once before extracting.
+    fn wants_checksums(&self) -> bool {
+        false
+    }
+
+    fn header_parsed(&mut self, _header: &ImageHeader<'_>) {}
+
+    fn partition_started(&mut self, _partition: &Partition) {}
+
+    /// Called after every chunk with the number of bytes of `partition` written so far.
+    fn bytes_written(&mut self, _partition: &Partition, _written: u64) {}
+
+    fn partition_finished(&mut self, _partition: &Partition) {}
+
+    fn warning(&mut self, _warning: &Warning) {}
+}
+
+impl UnpackObserver for () {}
+
+impl<O: UnpackObserver + ?Sized> UnpackObserver for &mut O {
+    fn wants_checksums(&self) -> bool {
+        (**self).wants_checksums()
+    }
+
+    fn header_parsed(&mut self, header: &ImageHeader<'_>) {
+        (**self).header_parsed(header)
+    }
+
+    fn partition_started(&mut self, partition: &Partition) {
+        (**self).partition_started(partition)
+    }
+
+    fn bytes_written(&mut self, partition: &Partition, written: u64) {
+        (**self).bytes_written(partition, written)
+    }
+
+    fn partition_finished(&mut self, partition: &Partition) {
+        (**self).partition_finished(partition)
+    }
+
+    fn warning(&mut self, warning: &Warning) {
+        (**self).warning(warning)
+    }
+}
+
+/// Reports every read from `inner` as bytes written to the sink.
+pub(crate) struct ProgressReader<'a, R: ?Sized, O: ?Sized> {
+    pub(crate) inner: &'a mut R,
+    pub(crate) observer: &'a mut O,
+    pub(crate) partition: &'a Partition,
+    pub(crate) written: u64,
+}
+
+impl<R: Read + ?Sized, O: UnpackObserver + ?Sized> Read for ProgressReader<'_, R, O> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n > 0 {
+            self.written += n as u64;
+            self.observer.bytes_written(self.partition, self.written);
+        }
+        Ok(n)
+    }
+}
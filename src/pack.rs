@@ -0,0 +1,292 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use crate::crc::RkCrc32;
+use crate::error::PathContext;
+use crate::mtdparts::PartitionTable;
+use crate::observer::{ImageKind, Partition};
+use crate::package::PackageFile;
+use crate::parameter::{unwrap_parameter, wrap_parameter, Parameter};
+use crate::rkfw::{BuildDate, ChipFamily, FirmwareVersion, RkfwHeader, RKFW_HEADER_LEN};
+use crate::{Error, Result, UpdateHeader, MAX_PARTS, PARM_MAGIC, RKAFP_MAGIC, RKAF_SIGNATURE, UPDATE_HEADER_LEN};
+
+/// Every part in an RKAF image starts on a 2 KiB boundary, like the header itself.
+const RKAF_ALIGN: u64 = UPDATE_HEADER_LEN as u64;
+/// `flash_offset` used by the vendor tools for parts that are not written to flash.
+const NO_FLASH_OFFSET: u32 = 0xffff_ffff;
+
+enum PartSource {
+    Reserved,
+    SelfImage,
+    File(PathBuf, u64),
+    Bytes(Vec<u8>),
+}
+
+/// Builds an RKAF update image from `src_dir`, the layout `unpack_file` produces:
+/// a `package-file` listing `name path` pairs and the files it refers to.
+/// Returns the parts written, in image order.
+pub fn pack_rkafp(src_dir: &str, dst_file: &str) -> Result<Vec<Partition>> {
+    let src = Path::new(src_dir);
+    let package_path = src.join("package-file");
+    let entries = PackageFile::from_bytes(&fs::read(&package_path).with_path(&package_path)?)?.entries;
+    if entries.len() > MAX_PARTS {
+        return Err(Error::Invalid(format!(
+            "package-file lists {} entries, RKAF supports at most {}",
+            entries.len(),
+            MAX_PARTS
+        )));
+    }
+
+    let mut header = UpdateHeader::default();
+    header.magic.copy_from_slice(RKAFP_MAGIC.as_bytes());
+    header.num_parts = entries.len() as u32;
+
+    let mut flash_parts = PartitionTable::default();
+    let mut sources = Vec::new();
+    for entry in &entries {
+        let (name, path) = (&entry.name, &entry.path);
+        let source = match path.as_str() {
+            "RESERVED" => PartSource::Reserved,
+            "SELF" => PartSource::SelfImage,
+            _ if name == "parameter" => {
+                let full_path = src.join(path);
+                let data = fs::read(&full_path).with_path(&full_path)?;
+                let text = if data.starts_with(PARM_MAGIC.as_bytes()) { unwrap_parameter(&data)? } else { &data };
+                let parameter = Parameter::from_bytes(text)?;
+                apply_parameter(&parameter, &mut header)?;
+                flash_parts = parameter.partition_table()?.unwrap_or_default();
+                PartSource::Bytes(wrap_parameter(text))
+            }
+            _ => {
+                let full_path = src.join(path);
+                let len = fs::metadata(&full_path).with_path(&full_path)?.len();
+                PartSource::File(full_path, len)
+            }
+        };
+        sources.push(source);
+    }
+
+    let mut offset = UPDATE_HEADER_LEN as u64;
+    for (i, (entry, source)) in entries.iter().zip(&sources).enumerate() {
+        let (name, path) = (&entry.name, &entry.path);
+        let part = &mut header.parts[i];
+        copy_cstr(&mut part.name, name)?;
+        copy_cstr(&mut part.full_path, path)?;
+
+        part.flash_offset = NO_FLASH_OFFSET;
+        if name == "parameter" {
+            part.flash_offset = 0;
+        } else if let Some(partition) = flash_parts.get(name) {
+            let sectors = |value: u64| {
+                u32::try_from(value).map_err(|_| Error::Invalid(format!("partition {} lies beyond 2 TiB", name)))
+            };
+            part.flash_offset = sectors(partition.start)?;
+            part.flash_size = sectors(partition.size.unwrap_or(0))?;
+        }
+
+        let len = match source {
+            PartSource::Reserved | PartSource::SelfImage => continue,
+            PartSource::File(_, len) => *len,
+            PartSource::Bytes(data) => data.len() as u64,
+        };
+        let padded = len.div_ceil(RKAF_ALIGN) * RKAF_ALIGN;
+        if offset + padded > u32::MAX as u64 {
+            return Err(Error::Invalid(format!("RKAF image would exceed 4 GiB at {}", path)));
+        }
+        part.part_offset = offset as u32;
+        part.part_byte_count = len as u32;
+        part.padded_size = padded as u32;
+        offset += padded;
+    }
+    header.length = offset as u32;
+    for (i, source) in sources.iter().enumerate() {
+        if let PartSource::SelfImage = source {
+            header.parts[i].part_byte_count = header.length + 4;
+            header.parts[i].padded_size = header.length + 4;
+        }
+    }
+
+    let mut out = HashWriter::new(
+        BufWriter::new(File::create(dst_file).with_path(dst_file)?),
+        RkCrc32::new(),
+    );
+    out.write_all(&header.to_bytes()).with_path(dst_file)?;
+    let mut written = Vec::new();
+    for (i, source) in sources.iter().enumerate() {
+        let part = &header.parts[i];
+        let (part_offset, len, padded) = (part.part_offset, part.part_byte_count, part.padded_size);
+        match source {
+            PartSource::Reserved | PartSource::SelfImage => continue,
+            PartSource::File(path, _) => {
+                let mut input = File::open(path).with_path(path)?.take(len as u64);
+                let copied = io::copy(&mut input, &mut out).with_path(dst_file)?;
+                if copied != len as u64 {
+                    return Err(Error::Invalid(format!("{} changed while packing", path.display())));
+                }
+            }
+            PartSource::Bytes(data) => out.write_all(data).with_path(dst_file)?,
+        }
+        io::copy(&mut io::repeat(0).take((padded - len) as u64), &mut out).with_path(dst_file)?;
+        written.push(Partition {
+            image: ImageKind::Rkaf,
+            path: entries[i].path.clone(),
+            display_path: entries[i].path.clone(),
+            offset: part_offset as u64,
+            len: len as u64,
+        });
+    }
+
+    let crc = out.hasher.finish();
+    let mut out = out.inner;
+    out.write_all(&crc.to_le_bytes()).with_path(dst_file)?;
+    out.flush().with_path(dst_file)?;
+    Ok(written)
+}
+
+/// Builds an RKFW firmware image, the equivalent of the vendor `img_maker`:
+/// header, `loader`, the RKAF image `update` and an MD5 trailer over all of it.
+/// The header bytes of unknown meaning are copied from `reference`, or zeroed without one.
+/// Returns the two parts written, `BOOT` and `embedded-update.img`.
+pub fn pack_rkfw(
+    loader: &str,
+    update: &str,
+    chip: ChipFamily,
+    version: FirmwareVersion,
+    date: BuildDate,
+    reference: Option<&RkfwHeader>,
+    dst_file: &str,
+) -> Result<Vec<Partition>> {
+    let loader_len = fs::metadata(loader).with_path(loader)?.len();
+    let mut update_file = File::open(update).with_path(update)?;
+    let update_len = update_file.metadata().with_path(update)?.len();
+
+    let mut magic = [0u8; 4];
+    update_file.read_exact(&mut magic).with_path(update)?;
+    if magic != RKAF_SIGNATURE {
+        return Err(Error::BadMagic {
+            what: "RKAF update image",
+            offset: 0,
+            expected: RKAF_SIGNATURE,
+            found: magic.to_vec(),
+        });
+    }
+
+    let loader_offset = RKFW_HEADER_LEN as u64;
+    let update_offset = loader_offset + loader_len;
+    if update_offset + update_len > u32::MAX as u64 {
+        return Err(Error::Invalid("RKFW image would exceed 4 GiB".to_string()));
+    }
+
+    let header = RkfwHeader {
+        version,
+        date,
+        chip: chip.code(),
+        loader_offset: loader_offset as u32,
+        loader_size: loader_len as u32,
+        update_offset: update_offset as u32,
+        update_size: update_len as u32,
+        unknown: reference.map_or([0u8; RKFW_HEADER_LEN - 0x29], |reference| reference.unknown),
+        ..RkfwHeader::default()
+    };
+
+    let mut out = HashWriter::new(
+        BufWriter::new(File::create(dst_file).with_path(dst_file)?),
+        md5::Context::new(),
+    );
+    out.write_all(&header.to_bytes()).with_path(dst_file)?;
+
+    let mut input = File::open(loader).with_path(loader)?.take(loader_len);
+    let copied = io::copy(&mut input, &mut out).with_path(dst_file)?;
+    if copied != loader_len {
+        return Err(Error::Invalid(format!("{} changed while packing", loader)));
+    }
+
+    update_file.rewind().with_path(update)?;
+    let copied = io::copy(&mut update_file.take(update_len), &mut out).with_path(dst_file)?;
+    if copied != update_len {
+        return Err(Error::Invalid(format!("{} changed while packing", update)));
+    }
+    let digest = out.hasher.compute();
+    let mut out = out.inner;
+    out.write_all(format!("{:x}", digest).as_bytes()).with_path(dst_file)?;
+    out.flush().with_path(dst_file)?;
+    let part = |path: &str, offset: u64, len: u64| Partition {
+        image: ImageKind::Rkfw,
+        path: path.to_string(),
+        display_path: path.to_string(),
+        offset,
+        len,
+    };
+    Ok(vec![part("BOOT", loader_offset, loader_len), part("embedded-update.img", update_offset, update_len)])
+}
+
+/// Copies the model, id, manufacturer and version from `parameter.txt` into the header.
+fn apply_parameter(parameter: &Parameter, header: &mut UpdateHeader) -> Result<()> {
+    if let Some(version) = parameter.firmware_ver {
+        header.version = version.to_u32();
+    }
+    if let Some(model) = &parameter.machine_model {
+        copy_cstr(&mut header.model, model)?;
+    }
+    if let Some(id) = parameter.get("MACHINE_ID") {
+        copy_cstr(&mut header.id, id)?;
+    }
+    if let Some(manufacturer) = &parameter.manufacturer {
+        copy_cstr(&mut header.manufacturer, manufacturer)?;
+    }
+    Ok(())
+}
+
+fn copy_cstr(dst: &mut [u8], s: &str) -> Result<()> {
+    if s.len() >= dst.len() {
+        return Err(Error::Invalid(format!(
+            "'{}' is too long, at most {} bytes allowed",
+            s,
+            dst.len() - 1
+        )));
+    }
+    dst.fill(0);
+    dst[..s.len()].copy_from_slice(s.as_bytes());
+    Ok(())
+}
+
+trait Hasher {
+    fn update(&mut self, data: &[u8]);
+}
+
+impl Hasher for RkCrc32 {
+    fn update(&mut self, data: &[u8]) {
+        RkCrc32::update(self, data)
+    }
+}
+
+impl Hasher for md5::Context {
+    fn update(&mut self, data: &[u8]) {
+        self.consume(data)
+    }
+}
+
+/// Feeds everything written through it to a checksum, for the trailer that
+/// follows the image.
+struct HashWriter<W: Write, H: Hasher> {
+    inner: W,
+    hasher: H,
+}
+
+impl<W: Write, H: Hasher> HashWriter<W, H> {
+    fn new(inner: W, hasher: H) -> Self {
+        Self { inner, hasher }
+    }
+}
+
+impl<W: Write, H: Hasher> Write for HashWriter<W, H> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
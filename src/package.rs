@@ -0,0 +1,155 @@
+use std::fmt;
+use std::str::FromStr;
+use crate::{Error, Result, UpdateHeader};
+
+/// One `name path` line of `package-file`, e.g. `boot Image/boot.img`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PackageEntry {
+    pub name: String,
+    /// Path relative to the unpacked image, or `RESERVED` / `SELF`.
+    pub path: String,
+}
+
+impl PackageEntry {
+    /// A placeholder such as `backup RESERVED` with no data of its own.
+    pub fn is_reserved(&self) -> bool {
+        self.path == "RESERVED"
+    }
+
+    /// Stands for the update image itself.
+    pub fn is_self(&self) -> bool {
+        self.path == "SELF"
+    }
+}
+
+/// The `package-file` manifest of an RKAF image: which file every part
+/// (`bootloader`, `parameter`, `boot`, ...) comes from, in image order.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PackageFile {
+    pub entries: Vec<PackageEntry>,
+}
+
+/// A disagreement between `package-file` and the part table of the image.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PackageIssue {
+    /// Listed in `package-file`, but no part has its name or path.
+    OnlyInPackage { name: String, path: String },
+    /// A part that `package-file` does not list.
+    OnlyInImage { name: String, path: String },
+    /// The part with this name comes from another file.
+    PathMismatch { name: String, package_path: String, image_path: String },
+    /// The part for this file has another name.
+    NameMismatch { path: String, package_name: String, image_name: String },
+    /// Listed with a file, but the part has no data inside the image.
+    MissingData { name: String, path: String },
+}
+
+impl fmt::Display for PackageIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PackageIssue::OnlyInPackage { name, path } => {
+                write!(f, "{} ({}) is listed in package-file but not in the image", name, path)
+            }
+            PackageIssue::OnlyInImage { name, path } => {
+                write!(f, "{} ({}) is in the image but not listed in package-file", name, path)
+            }
+            PackageIssue::PathMismatch { name, package_path, image_path } => {
+                write!(f, "{}: package-file says {}, image says {}", name, package_path, image_path)
+            }
+            PackageIssue::NameMismatch { path, package_name, image_name } => {
+                write!(f, "{}: package-file calls it {}, image calls it {}", path, package_name, image_name)
+            }
+            PackageIssue::MissingData { name, path } => write!(f, "{} ({}) has no data in the image", name, path),
+        }
+    }
+}
+
+impl PackageFile {
+    /// Parses `package-file`, which must be UTF-8.
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        std::str::from_utf8(data)
+            .map_err(|e| Error::Invalid(format!("package-file is not valid UTF-8: {}", e)))?
+            .parse()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&PackageEntry> {
+        self.entries.iter().find(|entry| entry.name == name)
+    }
+
+    /// Cross-checks the entries against the parts of `header`, read from an
+    /// image of `image_len` bytes. Parts are matched by name first, then by
+    /// path; an empty result means both list the same files.
+    pub fn check(&self, header: &UpdateHeader, image_len: u64) -> Vec<PackageIssue> {
+        let num_parts = (header.num_parts as usize).min(header.parts.len());
+        let parts: Vec<(String, String)> =
+            header.parts[..num_parts].iter().map(|part| (part.name(), part.path())).collect();
+        let mut matched = vec![None; self.entries.len()];
+        let mut used = vec![false; parts.len()];
+
+        for by_path in [false, true] {
+            for (entry, slot) in self.entries.iter().zip(matched.iter_mut()).filter(|(_, slot)| slot.is_none()) {
+                let wanted = |(name, path): &(String, String)| match by_path {
+                    false => entry.name == *name,
+                    true => entry.path == *path,
+                };
+                if let Some(i) = (0..parts.len()).find(|&i| !used[i] && wanted(&parts[i])) {
+                    used[i] = true;
+                    *slot = Some(i);
+                }
+            }
+        }
+
+        let mut issues = Vec::new();
+        for (entry, slot) in self.entries.iter().zip(&matched) {
+            let Some(i) = *slot else {
+                issues.push(PackageIssue::OnlyInPackage { name: entry.name.clone(), path: entry.path.clone() });
+                continue;
+            };
+            let (name, path) = &parts[i];
+            if entry.name != *name {
+                issues.push(PackageIssue::NameMismatch {
+                    path: path.clone(),
+                    package_name: entry.name.clone(),
+                    image_name: name.clone(),
+                });
+            } else if entry.path != *path {
+                issues.push(PackageIssue::PathMismatch {
+                    name: name.clone(),
+                    package_path: entry.path.clone(),
+                    image_path: path.clone(),
+                });
+            }
+            // RESERVED and SELF entries have no data of their own.
+            let part = &header.parts[i];
+            let end = part.part_offset as u64 + part.part_byte_count as u64;
+            if !entry.is_reserved() && !entry.is_self() && (part.part_offset == 0 || end > image_len) {
+                issues.push(PackageIssue::MissingData { name: entry.name.clone(), path: entry.path.clone() });
+            }
+        }
+        for ((name, path), _) in parts.iter().zip(&used).filter(|(_, used)| !**used) {
+            issues.push(PackageIssue::OnlyInImage { name: name.clone(), path: path.clone() });
+        }
+        issues
+    }
+}
+
+impl FromStr for PackageFile {
+    type Err = Error;
+
+    /// One `name path` pair per line; `#` starts a comment.
+    fn from_str(text: &str) -> Result<Self> {
+        let mut entries = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [name, path] = fields[..] else {
+                return Err(Error::Invalid(format!("package-file line {}: expected NAME PATH: {:?}", i + 1, line)));
+            };
+            entries.push(PackageEntry { name: name.to_string(), path: path.to_string() });
+        }
+        Ok(Self { entries })
+    }
+}
@@ -0,0 +1,118 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::SystemTime;
+use crate::sink::{FsSink, OutputSink};
+use crate::Result;
+
+/// Read size of each worker; large positioned reads keep fast disks busy.
+const READ_BUF_LEN: usize = 1 << 20;
+
+/// An image file and output directory that worker threads can share.
+#[derive(Clone, Debug)]
+pub(crate) struct ParallelTarget<'a> {
+    pub(crate) file: &'a File,
+    /// Offset of the image being unpacked within `file`.
+    pub(crate) base: u64,
+    pub(crate) sink: FsSink,
+}
+
+impl ParallelTarget<'_> {
+    /// The same target for an image embedded at `offset` of the current one.
+    pub(crate) fn nested(&self, offset: u64) -> Self {
+        Self { base: self.base + offset, ..self.clone() }
+    }
+
+    /// Writes `len` bytes at `offset` of the image to `path`, reading with its own
+    /// positioned reads so other threads can use the file at the same time.
+    pub(crate) fn copy(&self, offset: u64, len: u64, path: &str, mtime: Option<SystemTime>) -> Result<()> {
+        let reader = PositionedReader { file: self.file, pos: self.base + offset };
+        let mut data = BufReader::with_capacity(READ_BUF_LEN, reader).take(len);
+        self.sink.clone().write_file(path, len, mtime, &mut data)
+    }
+}
+
+/// Sequential reads at an explicit position that never touch the file cursor on Unix;
+/// targets without positioned reads seek under a lock instead.
+struct PositionedReader<'a> {
+    file: &'a File,
+    pos: u64,
+}
+
+impl Read for PositionedReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = read_at(self.file, buf, self.pos)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+#[cfg(unix)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::unix::fs::FileExt::read_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_read(file, buf, offset)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    use std::io::{Seek, SeekFrom};
+    use std::sync::Mutex;
+
+    // Every clone shares one cursor, so a seek and its read must not interleave.
+    static CURSOR: Mutex<()> = Mutex::new(());
+    let _guard = CURSOR.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let mut file = file.try_clone()?;
+    file.seek(SeekFrom::Start(offset))?;
+    file.read(buf)
+}
+
+/// Runs `work` for every job on up to `workers` threads and hands each result to
+/// `done` on the calling thread, in job order. Once `done` fails no new jobs are
+/// started and its error is returned.
+pub(crate) fn run_ordered<T, W, D>(jobs: &[T], workers: usize, work: W, mut done: D) -> Result<()>
+where
+    T: Sync,
+    W: Fn(usize, &T) -> Result<()> + Sync,
+    D: FnMut(usize, Result<()>) -> Result<()>,
+{
+    let next = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    thread::scope(|scope| {
+        let (tx, rx) = mpsc::channel();
+        for _ in 0..workers.clamp(1, jobs.len().max(1)) {
+            let tx = tx.clone();
+            let (next, stop, work) = (&next, &stop, &work);
+            scope.spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(job) = jobs.get(i) else { break };
+                    if tx.send((i, work(i, job))).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(tx);
+
+        // Results arrive in completion order; hold them until their turn.
+        let mut results: Vec<Option<Result<()>>> = jobs.iter().map(|_| None).collect();
+        for i in 0..jobs.len() {
+            while results[i].is_none() {
+                // Only fails if a worker panicked, which the scope re-raises.
+                let Ok((j, result)) = rx.recv() else { return Ok(()) };
+                results[j] = Some(result);
+            }
+            if let Err(err) = done(i, results[i].take().unwrap()) {
+                stop.store(true, Ordering::Relaxed);
+                return Err(err);
+            }
+        }
+        Ok(())
+    })
+}
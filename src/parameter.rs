@@ -0,0 +1,527 @@
+use std::fmt;
+use std::str::FromStr;
+use crate::crc::rkcrc32;
+use crate::mtdparts::PartitionTable;
+use crate::rkfw::FirmwareVersion;
+use crate::{Error, Result, PARM_MAGIC};
+
+/// On-disk size of `ParamHeader`.
+pub const PARAM_HEADER_LEN: usize = 8;
+/// Length of the RKCRC32 that follows the parameter text.
+const PARAM_CRC_LEN: usize = 4;
+
+/// Header of the binary envelope around `parameter.txt` in RKAF images:
+/// `PARM`, the text length, the text, then the RKCRC32 of the text.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ParamHeader {
+    pub magic: [u8; 4],
+    pub length: u32,
+}
+
+impl ParamHeader {
+    /// Header for `length` bytes of parameter text.
+    pub fn new(length: u32) -> Self {
+        let mut magic = [0u8; 4];
+        magic.copy_from_slice(PARM_MAGIC.as_bytes());
+        Self { magic, length }
+    }
+
+    /// Decodes the header, failing if `bytes` is too short or the magic is not `PARM`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < PARAM_HEADER_LEN {
+            return Err(Error::Truncated {
+                what: "PARM header".to_string(),
+                offset: 0,
+                needed: PARAM_HEADER_LEN as u64,
+                available: bytes.len() as u64,
+            });
+        }
+        if &bytes[..4] != PARM_MAGIC.as_bytes() {
+            return Err(Error::BadMagic {
+                what: "PARM header",
+                offset: 0,
+                expected: PARM_MAGIC.as_bytes(),
+                found: bytes[..4].to_vec(),
+            });
+        }
+        let mut header = Self::new(0);
+        header.length = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+        Ok(header)
+    }
+
+    pub fn to_bytes(&self) -> [u8; PARAM_HEADER_LEN] {
+        let mut buf = [0u8; PARAM_HEADER_LEN];
+        buf[..4].copy_from_slice(&self.magic);
+        buf[4..].copy_from_slice(&self.length.to_le_bytes());
+        buf
+    }
+}
+
+/// Returns the text inside a PARM envelope after checking its length and CRC.
+pub fn unwrap_parameter(data: &[u8]) -> Result<&[u8]> {
+    let header = ParamHeader::from_bytes(data)?;
+    let len = header.length as usize;
+    // The declared length can reach u32::MAX, which overflows usize on 32-bit targets.
+    let text_end = PARAM_HEADER_LEN.checked_add(len);
+    let text = text_end.and_then(|end| data.get(PARAM_HEADER_LEN..end)).ok_or_else(|| Error::Truncated {
+        what: "PARM body".to_string(),
+        offset: PARAM_HEADER_LEN as u64,
+        needed: header.length as u64,
+        available: (data.len() - PARAM_HEADER_LEN) as u64,
+    })?;
+    let text_end = PARAM_HEADER_LEN + text.len();
+    let crc = text_end
+        .checked_add(PARAM_CRC_LEN)
+        .and_then(|end| data.get(text_end..end))
+        .ok_or(Error::ChecksumMissing { what: "PARM CRC" })?;
+    let expected = u32::from_le_bytes(crc.try_into().unwrap());
+    let actual = rkcrc32(text);
+    if expected != actual {
+        return Err(Error::ChecksumMismatch {
+            what: "PARM CRC",
+            expected: format!("{:08x}", expected),
+            actual: format!("{:08x}", actual),
+        });
+    }
+    Ok(text)
+}
+
+/// Wraps parameter text in the PARM envelope: header, text, CRC.
+pub fn wrap_parameter(text: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(PARAM_HEADER_LEN + text.len() + PARAM_CRC_LEN);
+    data.extend_from_slice(&ParamHeader::new(text.len() as u32).to_bytes());
+    data.extend_from_slice(text);
+    data.extend_from_slice(&rkcrc32(text).to_le_bytes());
+    data
+}
+
+/// Keys with a typed field in `Parameter`, in the order Rockchip's own files use.
+const KNOWN_KEYS: [&str; 9] =
+    ["FIRMWARE_VER", "MACHINE_MODEL", "MANUFACTURER", "MAGIC", "ATAG", "MACHINE", "CHECK_MASK", "TYPE", "CMDLINE"];
+
+/// The `KEY: value` lines of `parameter.txt`. Keys the tools know about are
+/// typed; everything else, such as `MACHINE_ID` or `uuid:` lines, is kept in
+/// `other` in file order. The parsed text is kept as well, so that writing it
+/// back only touches the keys that changed; it takes no part in equality.
+#[derive(Clone, Debug, Default)]
+pub struct Parameter {
+    pub firmware_ver: Option<FirmwareVersion>,
+    pub machine_model: Option<String>,
+    pub manufacturer: Option<String>,
+    /// Usually `0x5041524B`, "PARK".
+    pub magic: Option<u32>,
+    /// Load address of the kernel tags.
+    pub atag: Option<u32>,
+    pub machine: Option<u32>,
+    pub check_mask: Option<u32>,
+    /// Partition table type, e.g. `GPT`.
+    pub table_type: Option<String>,
+    /// Kernel command line, including the `mtdparts=` partition layout.
+    pub cmdline: Option<String>,
+    pub other: Vec<(String, String)>,
+    /// Lines of the parsed text, each with its line ending.
+    lines: Vec<String>,
+    /// Text each typed field was read or set from, e.g. `11.0` or `3399`.
+    raw: Vec<(String, String)>,
+}
+
+impl Parameter {
+    /// Parses `parameter.txt`, with or without its PARM envelope.
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let text = if data.starts_with(PARM_MAGIC.as_bytes()) { unwrap_parameter(data)? } else { data };
+        let text = std::str::from_utf8(text)
+            .map_err(|e| Error::Invalid(format!("parameter.txt is not valid UTF-8: {}", e)))?;
+        text.parse()
+    }
+
+    /// The `mtdparts` layout in `CMDLINE`, with the uuids from `uuid:<name>=<uuid>`
+    /// lines filled in. `None` if `CMDLINE` is missing or has no `mtdparts=`.
+    pub fn partition_table(&self) -> Result<Option<PartitionTable>> {
+        let Some(cmdline) = &self.cmdline else {
+            return Ok(None);
+        };
+        if !cmdline.split_whitespace().any(|option| option.starts_with("mtdparts=")) {
+            return Ok(None);
+        }
+        let mut table = PartitionTable::from_cmdline(cmdline)?;
+        for (key, value) in &self.other {
+            if key != "uuid" {
+                continue;
+            }
+            let (name, uuid) = value
+                .split_once('=')
+                .ok_or_else(|| Error::Invalid(format!("Invalid uuid line '{}', expected uuid:<name>=<uuid>", value)))?;
+            let partition = table
+                .get_mut(name.trim())
+                .ok_or_else(|| Error::Invalid(format!("uuid given for unknown partition '{}'", name.trim())))?;
+            partition.uuid = Some(uuid.trim().to_string());
+        }
+        Ok(Some(table))
+    }
+
+    /// Value of a key that has no typed field, e.g. `MACHINE_ID`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.other.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+
+    /// Sets any key, parsing the value of typed ones as `from_str` does. Other
+    /// keys replace their existing line or are appended. `uuid:` lines belong
+    /// to the partitions and are written by `set_partition_table`.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        let invalid = |what: &str| Error::Invalid(format!("Cannot set {} to {:?}: {}", key, value, what));
+        if key.is_empty() || key.contains([':', '\n', '\r']) || key.trim() != key {
+            return Err(invalid("invalid key"));
+        }
+        if key == "uuid" {
+            return Err(invalid("uuids are set on the partition table"));
+        }
+        if value.contains(['\n', '\r']) {
+            return Err(invalid("value spans several lines"));
+        }
+        let value = value.trim();
+        if self.set_known(key, value).map_err(invalid)? {
+            return Ok(());
+        }
+        match self.other.iter_mut().find(|(k, _)| k == key) {
+            Some((_, v)) => *v = value.to_string(),
+            None => self.other.push((key.to_string(), value.to_string())),
+        }
+        Ok(())
+    }
+
+    /// Removes a key; returns whether it was set.
+    pub fn remove(&mut self, key: &str) -> bool {
+        let removed = match key {
+            "FIRMWARE_VER" => self.firmware_ver.take().is_some(),
+            "MACHINE_MODEL" => self.machine_model.take().is_some(),
+            "MANUFACTURER" => self.manufacturer.take().is_some(),
+            "MAGIC" => self.magic.take().is_some(),
+            "ATAG" => self.atag.take().is_some(),
+            "MACHINE" => self.machine.take().is_some(),
+            "CHECK_MASK" => self.check_mask.take().is_some(),
+            "TYPE" => self.table_type.take().is_some(),
+            "CMDLINE" => self.cmdline.take().is_some(),
+            _ => false,
+        };
+        self.raw.retain(|(k, _)| k != key);
+        let len = self.other.len();
+        self.other.retain(|(k, _)| k != key);
+        removed || self.other.len() != len
+    }
+
+    /// Writes `table` into the `mtdparts=` option of `CMDLINE` unless it has the
+    /// same layout already, and replaces the `uuid:` lines with the uuids set on
+    /// its partitions.
+    pub fn set_partition_table(&mut self, table: &PartitionTable) {
+        let layout = |table: &PartitionTable| {
+            let mut table = table.clone();
+            table.partitions.iter_mut().for_each(|partition| partition.uuid = None);
+            table
+        };
+        if self.partition_table().ok().flatten().map(|current| layout(&current)) != Some(layout(table)) {
+            self.put_bootarg("mtdparts", table.to_string());
+        }
+        self.other.retain(|(k, _)| k != "uuid");
+        for partition in &table.partitions {
+            if let Some(uuid) = &partition.uuid {
+                self.other.push(("uuid".to_string(), format!("{}={}", partition.name, uuid)));
+            }
+        }
+    }
+
+    /// Value of a kernel argument in `CMDLINE`, `Some("")` for a flag such as `rootwait`.
+    pub fn bootarg(&self, name: &str) -> Option<&str> {
+        self.cmdline
+            .as_deref()?
+            .split_whitespace()
+            .find(|arg| bootarg_name(arg) == name)
+            .map(|arg| arg.split_once('=').map_or("", |(_, value)| value))
+    }
+
+    /// Sets `name=value` in `CMDLINE`, or the flag `name` if `value` is empty.
+    /// An existing argument is replaced in place, a new one is appended.
+    pub fn set_bootarg(&mut self, name: &str, value: &str) -> Result<()> {
+        if name.is_empty() || name.contains(|c: char| c == '=' || c.is_whitespace()) {
+            return Err(Error::Invalid(format!("Invalid kernel argument name {:?}", name)));
+        }
+        if value.contains(char::is_whitespace) {
+            return Err(Error::Invalid(format!("Invalid value {:?} for kernel argument {}", value, name)));
+        }
+        let arg = match value {
+            "" => name.to_string(),
+            value => format!("{}={}", name, value),
+        };
+        self.put_bootarg(name, arg);
+        Ok(())
+    }
+
+    /// Removes every `name` or `name=...` argument from `CMDLINE`; returns whether there was one.
+    pub fn remove_bootarg(&mut self, name: &str) -> bool {
+        let Some(cmdline) = &self.cmdline else {
+            return false;
+        };
+        let args = bootargs(cmdline);
+        let kept: String = args
+            .iter()
+            .filter(|(_, arg)| bootarg_name(arg) != name)
+            .flat_map(|(separator, arg)| [*separator, *arg])
+            .collect();
+        if kept.len() == cmdline.len() {
+            return false;
+        }
+        self.cmdline = Some(kept.trim_start().to_string());
+        true
+    }
+
+    /// The parameter text in its PARM envelope, as flash tools expect it.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        wrap_parameter(self.to_string().as_bytes())
+    }
+
+    /// Replaces the first argument called `name` with `arg` and drops the rest,
+    /// or appends `arg`. The other arguments and the spacing between them are
+    /// left as they are.
+    fn put_bootarg(&mut self, name: &str, arg: String) {
+        let cmdline = self.cmdline.as_deref().unwrap_or_default();
+        let mut new = String::new();
+        let mut arg = Some(arg);
+        for (separator, existing) in bootargs(cmdline) {
+            if bootarg_name(existing) != name {
+                new += separator;
+                new += existing;
+            } else if let Some(arg) = arg.take() {
+                new += separator;
+                new += &arg;
+            }
+        }
+        if let Some(arg) = arg {
+            if !new.is_empty() {
+                new.push(' ');
+            }
+            new += &arg;
+        }
+        self.cmdline = Some(new.trim_start().to_string());
+    }
+
+    /// Text for the value of a typed key, `None` if it is not set. The text it
+    /// was read or set from is kept while it still means the same value.
+    fn known_value(&self, key: &str) -> Option<String> {
+        let value = self.formatted_value(key)?;
+        let Some((_, raw)) = self.raw.iter().find(|(k, _)| k == key) else {
+            return Some(value);
+        };
+        let mut parsed = Parameter::default();
+        match parsed.set_known(key, raw) {
+            Ok(true) if parsed.formatted_value(key) == Some(value.clone()) => Some(raw.clone()),
+            _ => Some(value),
+        }
+    }
+
+    /// Typed value of `key` as a fresh file would have it, numbers in hex.
+    fn formatted_value(&self, key: &str) -> Option<String> {
+        match key {
+            "FIRMWARE_VER" => self.firmware_ver.map(|version| version.to_string()),
+            "MACHINE_MODEL" => self.machine_model.clone(),
+            "MANUFACTURER" => self.manufacturer.clone(),
+            "MAGIC" => self.magic.map(|magic| format!("0x{:08X}", magic)),
+            "ATAG" => self.atag.map(|atag| format!("0x{:08X}", atag)),
+            "MACHINE" => self.machine.map(|machine| format!("{:#x}", machine)),
+            "CHECK_MASK" => self.check_mask.map(|check_mask| format!("{:#x}", check_mask)),
+            "TYPE" => self.table_type.clone(),
+            "CMDLINE" => self.cmdline.clone(),
+            _ => None,
+        }
+    }
+
+    /// Stores `value` in the typed field for `key`; `Ok(false)` if there is none.
+    fn set_known(&mut self, key: &str, value: &str) -> std::result::Result<bool, &'static str> {
+        let number = || parse_number(value).ok_or("invalid number");
+        match key {
+            "FIRMWARE_VER" => self.firmware_ver = Some(parse_firmware_ver(value).ok_or("invalid version")?),
+            "MACHINE_MODEL" => self.machine_model = Some(value.to_string()),
+            "MANUFACTURER" => self.manufacturer = Some(value.to_string()),
+            "MAGIC" => self.magic = Some(number()?),
+            "ATAG" => self.atag = Some(number()?),
+            "MACHINE" => self.machine = Some(number()?),
+            "CHECK_MASK" => self.check_mask = Some(number()?),
+            "TYPE" => self.table_type = Some(value.to_string()),
+            "CMDLINE" => self.cmdline = Some(value.to_string()),
+            _ => return Ok(false),
+        }
+        self.raw.retain(|(k, _)| k != key);
+        self.raw.push((key.to_string(), value.to_string()));
+        Ok(true)
+    }
+}
+
+impl PartialEq for Parameter {
+    /// Compares the values. Keys in `other` are compared in order per key, as
+    /// that is the only order `to_string` keeps.
+    fn eq(&self, rhs: &Self) -> bool {
+        fn by_key(parameter: &Parameter) -> Vec<&(String, String)> {
+            let mut other: Vec<&(String, String)> = parameter.other.iter().collect();
+            other.sort_by(|a, b| a.0.cmp(&b.0));
+            other
+        }
+        self.firmware_ver == rhs.firmware_ver
+            && self.machine_model == rhs.machine_model
+            && self.manufacturer == rhs.manufacturer
+            && self.magic == rhs.magic
+            && self.atag == rhs.atag
+            && self.machine == rhs.machine
+            && self.check_mask == rhs.check_mask
+            && self.table_type == rhs.table_type
+            && self.cmdline == rhs.cmdline
+            && by_key(self) == by_key(rhs)
+    }
+}
+
+impl Eq for Parameter {}
+
+impl fmt::Display for Parameter {
+    /// Writes the parsed text back line by line: comments, blank lines and
+    /// unchanged keys as they were, a changed key in place with its new value,
+    /// and a removed key not at all. Keys the text did not have follow in the
+    /// order Rockchip's own files use, with the keys from `other` after
+    /// `CHECK_MASK` (`MACHINE_ID` after `MACHINE_MODEL`) and the `uuid:` lines last.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let keys: Vec<Option<&str>> = self.lines.iter().map(|line| split_line(line).map(|(key, _, _)| key)).collect();
+        let line_count = |key: &str| keys.iter().filter(|k| **k == Some(key)).count();
+
+        // The n-th line of a key from `other` holds its n-th value.
+        let mut seen: Vec<&str> = Vec::new();
+        for (i, line) in self.lines.iter().enumerate() {
+            let Some((key, value, value_start)) = split_line(line) else {
+                f.write_str(line)?;
+                continue;
+            };
+            let current = if KNOWN_KEYS.contains(&key) {
+                // Like `from_str`, only the last line of a typed key counts.
+                match self.known_value(key) {
+                    Some(current) if !keys[i + 1..].contains(&Some(key)) => current,
+                    Some(_) => value.to_string(),
+                    None => continue,
+                }
+            } else {
+                let n = seen.iter().filter(|k| **k == key).count();
+                seen.push(key);
+                match self.other.iter().filter(|(k, _)| k == key).nth(n) {
+                    Some((_, current)) => current.clone(),
+                    None => continue,
+                }
+            };
+            if current == value {
+                f.write_str(line)?;
+            } else {
+                let content = line.trim_end_matches(['\r', '\n']);
+                write!(f, "{}{}{}", &content[..value_start], current, &line[content.len()..])?;
+            }
+        }
+
+        // Keys from `other` with more values than lines, and typed keys with no line.
+        let mut counted: Vec<&str> = Vec::new();
+        let unwritten: Vec<&(String, String)> = self
+            .other
+            .iter()
+            .filter(|(key, _)| {
+                counted.push(key);
+                counted.iter().filter(|k| **k == key).count() > line_count(key)
+            })
+            .collect();
+        let other_line = |(key, value): &&(String, String)| match key.as_str() {
+            "uuid" => format!("uuid:{}", value),
+            _ => format!("{}: {}", key, value),
+        };
+        let mut new = Vec::new();
+        for key in KNOWN_KEYS {
+            if key == "TYPE" {
+                new.extend(unwritten.iter().filter(|(k, _)| k != "MACHINE_ID" && k != "uuid").map(other_line));
+            }
+            if line_count(key) == 0 {
+                new.extend(self.known_value(key).map(|value| format!("{}: {}", key, value)));
+            }
+            if key == "MACHINE_MODEL" {
+                new.extend(unwritten.iter().filter(|(k, _)| k == "MACHINE_ID").map(other_line));
+            }
+        }
+        new.extend(unwritten.iter().filter(|(k, _)| k == "uuid").map(other_line));
+
+        if !new.is_empty() && self.lines.last().is_some_and(|line| !line.ends_with('\n')) {
+            writeln!(f)?;
+        }
+        for line in new {
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Parameter {
+    type Err = Error;
+
+    /// Blank lines and lines starting with `#` are skipped; every other line
+    /// must be `KEY: value`.
+    fn from_str(text: &str) -> Result<Self> {
+        let mut parameter = Parameter::default();
+        for (i, line) in text.split_inclusive('\n').enumerate() {
+            parameter.lines.push(line.to_string());
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+            let invalid = |what: &str| Error::Invalid(format!("parameter.txt line {}: {}: {:?}", i + 1, what, trimmed));
+            let (key, value, _) = split_line(line).ok_or_else(|| invalid("expected KEY: value"))?;
+            if !parameter.set_known(key, value).map_err(invalid)? {
+                parameter.other.push((key.to_string(), value.to_string()));
+            }
+        }
+        Ok(parameter)
+    }
+}
+
+/// Splits a `KEY: value` line into the key, the value and the offset the value
+/// starts at; `None` for blank lines, comments and lines without a colon.
+fn split_line(line: &str) -> Option<(&str, &str, usize)> {
+    let content = line.trim_end_matches(['\r', '\n']);
+    let trimmed = content.trim();
+    if trimmed.is_empty() || trimmed.starts_with('#') {
+        return None;
+    }
+    let (key, rest) = content.split_once(':')?;
+    let value = rest.trim_start();
+    Some((key.trim(), value.trim_end(), content.len() - value.len()))
+}
+
+/// Each argument of a kernel command line with the whitespace before it.
+fn bootargs(cmdline: &str) -> Vec<(&str, &str)> {
+    let mut args = Vec::new();
+    let mut rest = cmdline;
+    while !rest.trim_start().is_empty() {
+        let start = rest.len() - rest.trim_start().len();
+        let end = rest[start..].find(char::is_whitespace).map_or(rest.len(), |len| start + len);
+        args.push((&rest[..start], &rest[start..end]));
+        rest = &rest[end..];
+    }
+    args
+}
+
+/// Vendor files often leave out the last component, e.g. `11.0`.
+fn parse_firmware_ver(value: &str) -> Option<FirmwareVersion> {
+    match value.matches('.').count() {
+        1 => format!("{}.0", value).parse().ok(),
+        _ => value.parse().ok(),
+    }
+}
+
+/// `console=ttyFIQ0` is called `console`, a flag such as `rootwait` is its own name.
+fn bootarg_name(arg: &str) -> &str {
+    arg.split_once('=').map_or(arg, |(name, _)| name)
+}
+
+/// `0x`-prefixed hexadecimal or decimal.
+fn parse_number(value: &str) -> Option<u32> {
+    match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
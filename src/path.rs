@@ -0,0 +1,69 @@
+use std::fmt;
+
+/// Why a path from an image was refused.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PathRejection {
+    Empty,
+    /// The fixed-size field has no NUL terminator.
+    Unterminated,
+    NotUtf8,
+    /// Starts with `/` or a Windows drive letter.
+    Absolute,
+    /// Contains a `..` component.
+    ParentDir,
+    Backslash,
+    ControlChar,
+    /// An existing symbolic link in the output directory is in the way.
+    Symlink,
+}
+
+impl fmt::Display for PathRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PathRejection::Empty => "empty path",
+            PathRejection::Unterminated => "path is not NUL-terminated",
+            PathRejection::NotUtf8 => "path is not valid UTF-8",
+            PathRejection::Absolute => "absolute path",
+            PathRejection::ParentDir => "path escapes the output directory",
+            PathRejection::Backslash => "path contains a backslash",
+            PathRejection::ControlChar => "path contains control characters",
+            PathRejection::Symlink => "path goes through a symbolic link",
+        })
+    }
+}
+
+/// Extracts and checks the path stored in a NUL-terminated header field such as
+/// `UpdatePart::full_path`.
+pub fn sanitize_entry_path(field: &[u8]) -> Result<String, PathRejection> {
+    let len = field.iter().position(|&b| b == 0).ok_or(PathRejection::Unterminated)?;
+    let path = std::str::from_utf8(&field[..len]).map_err(|_| PathRejection::NotUtf8)?;
+    sanitize_relative_path(path)
+}
+
+/// Checks that `path` stays inside the output directory and normalises it to
+/// `/`-separated components without `.` or empty segments.
+pub fn sanitize_relative_path(path: &str) -> Result<String, PathRejection> {
+    if path.chars().any(char::is_control) {
+        return Err(PathRejection::ControlChar);
+    }
+    if path.contains('\\') {
+        return Err(PathRejection::Backslash);
+    }
+    let bytes = path.as_bytes();
+    if path.starts_with('/') || (bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':') {
+        return Err(PathRejection::Absolute);
+    }
+
+    let mut components = Vec::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => return Err(PathRejection::ParentDir),
+            _ => components.push(component),
+        }
+    }
+    if components.is_empty() {
+        return Err(PathRejection::Empty);
+    }
+    Ok(components.join("/"))
+}
@@ -0,0 +1,122 @@
+use std::io::{self, Read, Seek, SeekFrom};
+use crate::{Error, Result, UpdateHeader, NUM_PARTS_OFFSET, RKAF_SIGNATURE, UPDATE_HEADER_LEN};
+
+/// A `Read + Seek` view of `len` bytes starting at `offset` of another reader,
+/// e.g. an image embedded in a larger file or a partition of a disk dump.
+/// Positions are relative to `offset`.
+pub struct SubReader<R> {
+    inner: R,
+    offset: u64,
+    len: u64,
+    pos: u64,
+}
+
+impl<R: Read + Seek> SubReader<R> {
+    pub fn new(inner: R, offset: u64, len: u64) -> Self {
+        Self { inner, offset, len, pos: 0 }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read + Seek> Read for SubReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.len.saturating_sub(self.pos);
+        let want = std::cmp::min(buf.len() as u64, remaining) as usize;
+        if want == 0 {
+            return Ok(0);
+        }
+        let pos = self.offset.checked_add(self.pos).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "position overflows the underlying reader")
+        })?;
+        self.inner.seek(SeekFrom::Start(pos))?;
+        let n = self.inner.read(&mut buf[..want])?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Read + Seek> Seek for SubReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(p) => Some(p),
+            SeekFrom::End(d) => self.len.checked_add_signed(d),
+            SeekFrom::Current(d) => self.pos.checked_add_signed(d),
+        };
+        match new_pos {
+            Some(p) => {
+                self.pos = p;
+                Ok(p)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
+
+/// Fills `buf` from the start of a `filesize`-byte stream, failing with
+/// `Error::Truncated` instead of an I/O error when the stream is too short.
+pub(crate) fn read_header<R: Read + Seek>(reader: &mut R, buf: &mut [u8], filesize: u64, what: &str) -> Result<()> {
+    if filesize < buf.len() as u64 {
+        return Err(Error::Truncated {
+            what: what.to_string(),
+            offset: 0,
+            needed: buf.len() as u64,
+            available: filesize,
+        });
+    }
+    reader.seek(SeekFrom::Start(0))?;
+    reader.read_exact(buf)?;
+    Ok(())
+}
+
+/// Reads and decodes the RKAF header at the start of a `filesize`-byte stream.
+/// A `num_parts` above `MAX_PARTS` is read best-effort: the table is assumed to
+/// continue past the first 2 KiB, which is a guess and not a documented layout.
+pub(crate) fn read_update_header<R: Read + Seek>(reader: &mut R, filesize: u64) -> Result<UpdateHeader> {
+    let mut buf = vec![0u8; UPDATE_HEADER_LEN];
+    read_header(reader, &mut buf, filesize, "RKAF header")?;
+    if &buf[..4] != RKAF_SIGNATURE {
+        return Err(Error::BadMagic {
+            what: "RKAF header",
+            offset: 0,
+            expected: RKAF_SIGNATURE,
+            found: buf[..4].to_vec(),
+        });
+    }
+
+    let num_parts = u32::from_le_bytes(buf[NUM_PARTS_OFFSET..NUM_PARTS_OFFSET + 4].try_into().unwrap());
+    let len = UpdateHeader::encoded_len(num_parts)?;
+    if len > buf.len() {
+        buf.resize(len, 0);
+        read_header(reader, &mut buf, filesize, "RKAF extended part table")?;
+    }
+    let header = UpdateHeader::from_bytes(&buf)?;
+
+    // A bogus count would make the table run into partition data.
+    if len > UPDATE_HEADER_LEN {
+        let first_data = header.parts
+            .iter()
+            .map(|part| part.part_offset)
+            .filter(|&offset| offset != 0)
+            .min();
+        if let Some(offset) = first_data.filter(|&offset| (offset as usize) < len) {
+            return Err(Error::InvalidPartCount {
+                count: num_parts,
+                reason: format!("the part table would overlap partition data at {:#x}", offset),
+            });
+        }
+    }
+    Ok(header)
+}
+
+/// Total length of a seekable stream; leaves the position at the start.
+pub(crate) fn stream_len<R: Seek>(reader: &mut R) -> io::Result<u64> {
+    let len = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+    Ok(len)
+}
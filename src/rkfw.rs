@@ -0,0 +1,448 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::{Error, Result, RKFW_SIGNATURE};
+
+/// Size of the RKFW header; the loader follows immediately after it.
+pub const RKFW_HEADER_LEN: usize = 0x66;
+/// Value found in the `code` field of vendor-built RKFW images.
+const RKFW_CODE: u32 = 0x0103_0000;
+
+/// Chip family stored at 0x15 of the RKFW header.
+///
+/// Old images carry a device type (0x50 for RK29xx ... 0x80 for RK32xx). Images made by
+/// rkImageMaker carry the last four characters of its chip tag (e.g. `330C` for `-RK330C`,
+/// RK3399), stored reversed so the low byte is the last character.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ChipFamily {
+    Rk29xx,
+    Rk30xx,
+    Rk31xx,
+    Rk32xx,
+    Rk3036,
+    Rk3128,
+    Rk3288,
+    Rk3308,
+    Rk3326,
+    Px30,
+    Rk3328,
+    Rk3368,
+    Rk3399,
+    Rk3528,
+    Rk3562,
+    /// Images of older tools, which write the single byte 0x38 and have always been shown as RK3566.
+    Rk3566,
+    /// The `3568` tag, which rkImageMaker also uses for RK3566.
+    Rk3568,
+    Rk3576,
+    Rk3588,
+    /// Shared by RV1126 and RV1109.
+    Rv1126,
+    Unknown(u32),
+}
+
+const fn tag(chars: &[u8; 4]) -> u32 {
+    u32::from_be_bytes(*chars)
+}
+
+/// Every known family with its chip field value and name.
+const CHIP_FAMILIES: &[(ChipFamily, u32, &str)] = &[
+    (ChipFamily::Rk29xx, 0x50, "RK29xx"),
+    (ChipFamily::Rk30xx, 0x60, "RK30xx"),
+    (ChipFamily::Rk31xx, 0x70, "RK31xx"),
+    (ChipFamily::Rk32xx, 0x80, "RK32xx"),
+    (ChipFamily::Rk3036, tag(b"303A"), "RK3036"),
+    (ChipFamily::Rk3128, tag(b"312A"), "RK3128"),
+    (ChipFamily::Rk3288, tag(b"320A"), "RK3288"),
+    (ChipFamily::Rk3308, tag(b"3308"), "RK3308"),
+    (ChipFamily::Rk3326, tag(b"3326"), "RK3326"),
+    (ChipFamily::Px30, tag(b"PX30"), "PX30"),
+    (ChipFamily::Rk3328, tag(b"322H"), "RK3328"),
+    (ChipFamily::Rk3368, tag(b"330A"), "RK3368"),
+    (ChipFamily::Rk3399, tag(b"330C"), "RK3399"),
+    (ChipFamily::Rk3528, tag(b"3528"), "RK3528"),
+    (ChipFamily::Rk3562, tag(b"3562"), "RK3562"),
+    (ChipFamily::Rk3566, 0x38, "RK3566"),
+    (ChipFamily::Rk3568, tag(b"3568"), "RK3568"),
+    (ChipFamily::Rk3576, tag(b"3576"), "RK3576"),
+    (ChipFamily::Rk3588, tag(b"3588"), "RK3588"),
+    (ChipFamily::Rv1126, tag(b"1126"), "RV1126"),
+];
+
+/// Other names accepted by `ChipFamily::from_name`.
+const CHIP_ALIASES: &[(&str, ChipFamily)] = &[
+    ("RV1109", ChipFamily::Rv1126),
+];
+
+/// Single-byte codes written by older tools, which only set the low byte of the chip field.
+const LEGACY_CHIP_CODES: &[(u8, ChipFamily)] = &[
+    (0x41, ChipFamily::Rk3368),
+    (0x36, ChipFamily::Rk3326),
+    (0x30, ChipFamily::Px30),
+];
+
+impl ChipFamily {
+    /// Every known family, in table order.
+    pub fn all() -> impl Iterator<Item = ChipFamily> {
+        CHIP_FAMILIES.iter().map(|(family, _, _)| *family)
+    }
+
+    /// Identifies the family from the full chip field; unknown values are kept as `Unknown`.
+    pub fn from_code(code: u32) -> Self {
+        if let Some((family, _, _)) = CHIP_FAMILIES.iter().find(|(_, c, _)| *c == code) {
+            return *family;
+        }
+        if code <= 0xff {
+            if let Some((_, family)) = LEGACY_CHIP_CODES.iter().find(|(c, _)| *c as u32 == code) {
+                return *family;
+            }
+        }
+        ChipFamily::Unknown(code)
+    }
+
+    /// Value written to the chip field.
+    pub fn code(self) -> u32 {
+        match self {
+            ChipFamily::Unknown(code) => code,
+            family => CHIP_FAMILIES
+                .iter()
+                .find(|(f, _, _)| *f == family)
+                .map(|(_, code, _)| *code)
+                .unwrap_or(0),
+        }
+    }
+
+    /// Family name, `None` for unknown codes.
+    pub fn name(self) -> Option<&'static str> {
+        CHIP_FAMILIES
+            .iter()
+            .find(|(f, _, _)| *f == self)
+            .map(|(_, _, name)| *name)
+    }
+
+    /// Looks up a family by name (case-insensitive) or accepts a raw hex code like `0x30`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.trim();
+        if let Some(hex) = name.strip_prefix("0x").or_else(|| name.strip_prefix("0X")) {
+            return u32::from_str_radix(hex, 16).ok().map(Self::from_code);
+        }
+        CHIP_FAMILIES
+            .iter()
+            .find(|(_, _, n)| n.eq_ignore_ascii_case(name))
+            .map(|(family, _, _)| *family)
+            .or_else(|| {
+                CHIP_ALIASES
+                    .iter()
+                    .find(|(n, _)| n.eq_ignore_ascii_case(name))
+                    .map(|(_, family)| *family)
+            })
+    }
+}
+
+impl fmt::Display for ChipFamily {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name() {
+            Some(name) => f.write_str(name),
+            None => write!(f, "unknown ({:#x})", self.code()),
+        }
+    }
+}
+
+impl FromStr for ChipFamily {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::from_name(s).ok_or_else(|| Error::Invalid(format!("Unknown chip family '{}'", s)))
+    }
+}
+
+/// Parsed RKFW header, the first `RKFW_HEADER_LEN` bytes of an RKFW image.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RkfwHeader {
+    pub magic: [u8; 4],
+    pub header_len: u16,
+    pub version: FirmwareVersion,
+    pub code: u32,
+    pub date: BuildDate,
+    /// Chip field at 0x15, see `ChipFamily`.
+    pub chip: u32,
+    pub loader_offset: u32,
+    pub loader_size: u32,
+    pub update_offset: u32,
+    pub update_size: u32,
+    /// Bytes 0x29..0x66, whose meaning is not known.
+    pub unknown: [u8; RKFW_HEADER_LEN - 0x29],
+}
+
+impl Default for RkfwHeader {
+    fn default() -> Self {
+        Self {
+            magic: *b"RKFW",
+            header_len: RKFW_HEADER_LEN as u16,
+            version: FirmwareVersion::default(),
+            code: RKFW_CODE,
+            date: BuildDate::default(),
+            chip: 0,
+            loader_offset: RKFW_HEADER_LEN as u32,
+            loader_size: 0,
+            update_offset: RKFW_HEADER_LEN as u32,
+            update_size: 0,
+            unknown: [0u8; RKFW_HEADER_LEN - 0x29],
+        }
+    }
+}
+
+impl RkfwHeader {
+    pub fn from_bytes(buf: &[u8]) -> Result<Self> {
+        if buf.len() < RKFW_HEADER_LEN {
+            return Err(Error::Truncated {
+                what: "RKFW header".to_string(),
+                offset: 0,
+                needed: RKFW_HEADER_LEN as u64,
+                available: buf.len() as u64,
+            });
+        }
+        if &buf[0..4] != RKFW_SIGNATURE {
+            return Err(Error::BadMagic {
+                what: "RKFW header",
+                offset: 0,
+                expected: RKFW_SIGNATURE,
+                found: buf[0..4].to_vec(),
+            });
+        }
+
+        let u32_at = |off: usize| u32::from_le_bytes([buf[off], buf[off + 1], buf[off + 2], buf[off + 3]]);
+        let mut unknown = [0u8; RKFW_HEADER_LEN - 0x29];
+        unknown.copy_from_slice(&buf[0x29..RKFW_HEADER_LEN]);
+        Ok(Self {
+            magic: [buf[0], buf[1], buf[2], buf[3]],
+            header_len: u16::from_le_bytes([buf[4], buf[5]]),
+            version: FirmwareVersion::from_u32(u32_at(0x06)),
+            code: u32_at(0x0a),
+            date: BuildDate {
+                year: u16::from_le_bytes([buf[0x0e], buf[0x0f]]),
+                month: buf[0x10],
+                day: buf[0x11],
+                hour: buf[0x12],
+                minute: buf[0x13],
+                second: buf[0x14],
+            },
+            chip: u32_at(0x15),
+            loader_offset: u32_at(0x19),
+            loader_size: u32_at(0x1d),
+            update_offset: u32_at(0x21),
+            update_size: u32_at(0x25),
+            unknown,
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; RKFW_HEADER_LEN] {
+        let mut buf = [0u8; RKFW_HEADER_LEN];
+        buf[0..4].copy_from_slice(&self.magic);
+        buf[0x04..0x06].copy_from_slice(&self.header_len.to_le_bytes());
+        buf[0x06..0x0a].copy_from_slice(&self.version.to_u32().to_le_bytes());
+        buf[0x0a..0x0e].copy_from_slice(&self.code.to_le_bytes());
+        buf[0x0e..0x15].copy_from_slice(&self.date.to_bytes());
+        buf[0x15..0x19].copy_from_slice(&self.chip.to_le_bytes());
+        buf[0x19..0x1d].copy_from_slice(&self.loader_offset.to_le_bytes());
+        buf[0x1d..0x21].copy_from_slice(&self.loader_size.to_le_bytes());
+        buf[0x21..0x25].copy_from_slice(&self.update_offset.to_le_bytes());
+        buf[0x25..0x29].copy_from_slice(&self.update_size.to_le_bytes());
+        buf[0x29..].copy_from_slice(&self.unknown);
+        buf
+    }
+
+    pub fn chip_family(&self) -> ChipFamily {
+        ChipFamily::from_code(self.chip)
+    }
+
+    /// End of the embedded update image, where the MD5 trailer starts.
+    pub fn body_len(&self) -> u64 {
+        self.update_offset as u64 + self.update_size as u64
+    }
+}
+
+/// Firmware version as stored in RKFW and RKAF headers: `major.minor.small`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct FirmwareVersion {
+    pub major: u8,
+    pub minor: u8,
+    pub small: u16,
+}
+
+impl FirmwareVersion {
+    pub fn from_u32(value: u32) -> Self {
+        Self {
+            major: (value >> 24) as u8,
+            minor: (value >> 16) as u8,
+            small: value as u16,
+        }
+    }
+
+    pub fn to_u32(self) -> u32 {
+        ((self.major as u32) << 24) | ((self.minor as u32) << 16) | self.small as u32
+    }
+}
+
+impl fmt::Display for FirmwareVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.small)
+    }
+}
+
+impl FromStr for FirmwareVersion {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::Invalid(format!("Invalid firmware version '{}', expected major.minor.small", s));
+        let mut nums = s.trim().split('.');
+        let (Some(major), Some(minor), Some(small), None) =
+            (nums.next(), nums.next(), nums.next(), nums.next())
+        else {
+            return Err(invalid());
+        };
+        Ok(Self {
+            major: major.trim().parse().map_err(|_| invalid())?,
+            minor: minor.trim().parse().map_err(|_| invalid())?,
+            small: small.trim().parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// Build timestamp stored at 0x0e in the RKFW header.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct BuildDate {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl BuildDate {
+    /// Current UTC time.
+    pub fn now() -> Self {
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        Self::from_unix(secs)
+    }
+
+    pub fn from_unix(secs: u64) -> Self {
+        let days = (secs / 86400) as i64;
+        let rem = secs % 86400;
+        let (year, month, day) = civil_from_days(days);
+        Self {
+            year: year as u16,
+            month,
+            day,
+            hour: (rem / 3600) as u8,
+            minute: (rem / 60 % 60) as u8,
+            second: (rem % 60) as u8,
+        }
+    }
+
+    /// True when every field is in range; vendor images sometimes leave the date zeroed.
+    pub fn is_valid(&self) -> bool {
+        self.year >= 1970
+            && (1..=12).contains(&self.month)
+            && (1..=days_in_month(self.year, self.month)).contains(&self.day)
+            && self.hour <= 23
+            && self.minute <= 59
+            && self.second <= 59
+    }
+
+    /// The date as a `SystemTime`, treating it as UTC since the header carries no time zone.
+    pub fn to_system_time(&self) -> Option<SystemTime> {
+        if !self.is_valid() {
+            return None;
+        }
+        let days = days_from_civil(self.year as i64, self.month, self.day);
+        let secs = days * 86400 + self.hour as i64 * 3600 + self.minute as i64 * 60 + self.second as i64;
+        Some(UNIX_EPOCH + Duration::from_secs(secs as u64))
+    }
+
+    pub fn to_bytes(self) -> [u8; 7] {
+        let year = self.year.to_le_bytes();
+        [year[0], year[1], self.month, self.day, self.hour, self.minute, self.second]
+    }
+}
+
+impl fmt::Display for BuildDate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+impl FromStr for BuildDate {
+    type Err = Error;
+
+    /// Accepts `YYYY-MM-DD`, `YYYY-MM-DD HH:MM:SS` and `YYYY-MM-DDTHH:MM:SS`.
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::Invalid(format!("Invalid build date '{}', expected YYYY-MM-DD HH:MM:SS", s));
+        let s = s.trim();
+        let (date, time) = match s.split_once([' ', 'T']) {
+            Some((date, time)) => (date, time.trim()),
+            None => (s, "00:00:00"),
+        };
+        let date: Vec<&str> = date.split('-').collect();
+        let time: Vec<&str> = time.split(':').collect();
+        if date.len() != 3 || time.len() != 3 {
+            return Err(invalid());
+        }
+        let parsed = Self {
+            year: date[0].parse().map_err(|_| invalid())?,
+            month: date[1].parse().map_err(|_| invalid())?,
+            day: date[2].parse().map_err(|_| invalid())?,
+            hour: time[0].parse().map_err(|_| invalid())?,
+            minute: time[1].parse().map_err(|_| invalid())?,
+            second: time[2].parse().map_err(|_| invalid())?,
+        };
+        if !parsed.is_valid() {
+            return Err(invalid());
+        }
+        Ok(parsed)
+    }
+}
+
+/// Number of days in `month` (1-12) of the Gregorian `year`; 0 for any other month.
+fn days_in_month(year: u16, month: u8) -> u8 {
+    let leap = year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400));
+    match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if leap => 29,
+        2 => 28,
+        _ => 0,
+    }
+}
+
+// Howard Hinnant's conversions between days since the epoch and proleptic Gregorian dates.
+fn civil_from_days(days: i64) -> (i64, u8, u8) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn days_from_civil(year: i64, month: u8, day: u8) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
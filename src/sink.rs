@@ -0,0 +1,278 @@
+use std::collections::BTreeMap;
+use std::fs::{self, create_dir_all, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::error::PathContext;
+use crate::path::{sanitize_relative_path, PathRejection};
+use crate::{Error, Result};
+
+/// Destination for extracted files. Paths are relative to the output root and
+/// use `/` separators, e.g. `Image/boot.img` or `BOOT`.
+pub trait OutputSink {
+    /// Stores the file `path`, reading exactly `len` bytes from `data`.
+    fn write_file(&mut self, path: &str, len: u64, mtime: Option<SystemTime>, data: &mut dyn Read) -> Result<()>;
+
+    /// How `path` is shown in progress output.
+    fn display_path(&self, path: &str) -> String {
+        path.to_string()
+    }
+}
+
+impl<S: OutputSink + ?Sized> OutputSink for &mut S {
+    fn write_file(&mut self, path: &str, len: u64, mtime: Option<SystemTime>, data: &mut dyn Read) -> Result<()> {
+        (**self).write_file(path, len, mtime, data)
+    }
+
+    fn display_path(&self, path: &str) -> String {
+        (**self).display_path(path)
+    }
+}
+
+/// Granularity at which zero-filled data is left as a hole in sparse files.
+const SPARSE_BLOCK_LEN: usize = 4096;
+const SPARSE_BUF_LEN: usize = 64 * SPARSE_BLOCK_LEN;
+
+/// Writes files below a directory, creating subdirectories as needed. Paths that
+/// would leave the directory or go through a symbolic link are refused.
+#[derive(Clone, Debug)]
+pub struct FsSink {
+    root: PathBuf,
+    sparse: bool,
+}
+
+impl FsSink {
+    /// Files are written sparse by default on Linux, see `with_sparse`.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into(), sparse: cfg!(target_os = "linux") }
+    }
+
+    /// Whether to seek over zero-filled blocks instead of writing them, leaving
+    /// holes in the output files.
+    pub fn with_sparse(mut self, sparse: bool) -> Self {
+        self.sparse = sparse;
+        self
+    }
+}
+
+impl OutputSink for FsSink {
+    fn write_file(&mut self, path: &str, len: u64, mtime: Option<SystemTime>, data: &mut dyn Read) -> Result<()> {
+        let unsafe_path = |reason| Error::UnsafePath { path: path.to_string(), reason };
+        let relative = sanitize_relative_path(path).map_err(unsafe_path)?;
+
+        create_dir_all(&self.root).with_path(&self.root)?;
+        let mut full_path = self.root.clone();
+        let mut components = relative.split('/').peekable();
+        while let Some(component) = components.next() {
+            full_path.push(component);
+            let is_last = components.peek().is_none();
+            match fs::symlink_metadata(&full_path) {
+                Ok(meta) if meta.file_type().is_symlink() => return Err(unsafe_path(PathRejection::Symlink)),
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound && !is_last => {
+                    create_dir(&full_path)?;
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(Error::File { path: full_path, source: e }),
+            }
+        }
+        let mut file = File::create(&full_path).with_path(&full_path)?;
+        if self.sparse {
+            copy_sparse(data, &mut file, len, path)?;
+        } else {
+            copy_exact(data, &mut file, len, path)?;
+        }
+        if let Some(mtime) = mtime {
+            file.set_modified(mtime).with_path(&full_path)?;
+        }
+        Ok(())
+    }
+
+    fn display_path(&self, path: &str) -> String {
+        self.root.join(path).display().to_string()
+    }
+}
+
+/// Keeps extracted files in memory, keyed by path.
+#[derive(Clone, Debug, Default)]
+pub struct MemorySink {
+    pub files: BTreeMap<String, Vec<u8>>,
+}
+
+impl MemorySink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, path: &str) -> Option<&[u8]> {
+        self.files.get(path).map(Vec::as_slice)
+    }
+}
+
+impl OutputSink for MemorySink {
+    fn write_file(&mut self, path: &str, len: u64, _mtime: Option<SystemTime>, data: &mut dyn Read) -> Result<()> {
+        let mut buf = Vec::with_capacity(len as usize);
+        copy_exact(data, &mut buf, len, path)?;
+        self.files.insert(path.to_string(), buf);
+        Ok(())
+    }
+}
+
+/// Appends extracted files to a tar archive.
+pub struct TarSink<W: Write> {
+    builder: tar::Builder<W>,
+}
+
+impl<W: Write> TarSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self { builder: tar::Builder::new(writer) }
+    }
+
+    /// Writes the end-of-archive marker and returns the underlying writer.
+    pub fn into_inner(self) -> Result<W> {
+        Ok(self.builder.into_inner()?)
+    }
+}
+
+impl<W: Write> OutputSink for TarSink<W> {
+    fn write_file(&mut self, path: &str, len: u64, mtime: Option<SystemTime>, data: &mut dyn Read) -> Result<()> {
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_size(len);
+        header.set_mode(0o644);
+        let secs = mtime
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        header.set_mtime(secs);
+        // The header announces `len` bytes, so refuse short input instead of writing a corrupt archive.
+        let mut data = ExactReader { inner: data, remaining: len };
+        self.builder.append_data(&mut header, path, &mut data)?;
+        Ok(())
+    }
+}
+
+/// Reads and discards everything, for checking that an image extracts cleanly.
+#[derive(Clone, Debug, Default)]
+pub struct NullSink {
+    /// Number of files seen.
+    pub files: usize,
+    /// Total bytes read.
+    pub bytes: u64,
+}
+
+impl NullSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl OutputSink for NullSink {
+    fn write_file(&mut self, path: &str, len: u64, _mtime: Option<SystemTime>, data: &mut dyn Read) -> Result<()> {
+        copy_exact(data, &mut io::sink(), len, path)?;
+        self.files += 1;
+        self.bytes += len;
+        Ok(())
+    }
+}
+
+fn create_dir(path: &Path) -> Result<()> {
+    match fs::create_dir(path) {
+        Err(e) if e.kind() != io::ErrorKind::AlreadyExists => Err(Error::File { path: path.to_path_buf(), source: e }),
+        _ => Ok(()),
+    }
+}
+
+fn copy_exact(data: &mut dyn Read, out: &mut dyn Write, len: u64, path: &str) -> Result<()> {
+    let copied = io::copy(&mut data.take(len), out)?;
+    if copied != len {
+        return Err(Error::Truncated {
+            what: path.to_string(),
+            offset: copied,
+            needed: len,
+            available: copied,
+        });
+    }
+    Ok(())
+}
+
+/// Like `copy_exact`, but seeks over zero-filled blocks instead of writing them.
+fn copy_sparse(data: &mut dyn Read, file: &mut File, len: u64, path: &str) -> Result<()> {
+    let mut buf = vec![0u8; SPARSE_BUF_LEN];
+    let mut copied = 0u64;
+    let mut hole = 0u64;
+    while copied < len {
+        let want = std::cmp::min(buf.len() as u64, len - copied) as usize;
+        let n = read_full(data, &mut buf[..want])?;
+        if n == 0 {
+            break;
+        }
+        // Write each run of non-zero blocks in one go.
+        let mut run_start = None;
+        for (i, block) in buf[..n].chunks(SPARSE_BLOCK_LEN).enumerate() {
+            let start = i * SPARSE_BLOCK_LEN;
+            if block.iter().any(|&b| b != 0) {
+                if run_start.is_none() {
+                    file.seek(SeekFrom::Current(hole as i64))?;
+                    hole = 0;
+                    run_start = Some(start);
+                }
+            } else {
+                if let Some(run_start) = run_start.take() {
+                    file.write_all(&buf[run_start..start])?;
+                }
+                hole += block.len() as u64;
+            }
+        }
+        if let Some(run_start) = run_start {
+            file.write_all(&buf[run_start..n])?;
+        }
+        copied += n as u64;
+    }
+    if copied != len {
+        return Err(Error::Truncated {
+            what: path.to_string(),
+            offset: copied,
+            needed: len,
+            available: copied,
+        });
+    }
+    // A trailing hole is only a seek so far; extend the file over it.
+    file.set_len(len)?;
+    Ok(())
+}
+
+/// Reads until `buf` is full or the input ends, so blocks stay aligned.
+fn read_full(data: &mut dyn Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match data.read(&mut buf[n..]) {
+            Ok(0) => break,
+            Ok(read) => n += read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(n)
+}
+
+/// Yields at most `remaining` bytes and fails if the source ends sooner.
+struct ExactReader<'a> {
+    inner: &'a mut dyn Read,
+    remaining: u64,
+}
+
+impl Read for ExactReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 {
+            return Ok(0);
+        }
+        let want = std::cmp::min(buf.len() as u64, self.remaining) as usize;
+        let n = self.inner.read(&mut buf[..want])?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+}
@@ -0,0 +1,225 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use crate::crc::RkCrc32;
+use crate::error::PathContext;
+use crate::package::{PackageFile, PackageIssue};
+use crate::reader::{read_header, read_update_header, stream_len, SubReader};
+use crate::rkfw::{RkfwHeader, RKFW_HEADER_LEN};
+use crate::{rkfp_unsupported, Error, Result, UpdateHeader, RKAF_SIGNATURE, RKFP_SIGNATURE, RKFW_SIGNATURE};
+
+const MD5_TRAILER_LEN: u64 = 32;
+/// `package-file` is a few hundred bytes; anything this large is not one.
+const MAX_PACKAGE_FILE_LEN: u64 = 1 << 20;
+
+/// Outcome of checking the RKCRC trailer of an RKAF image.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CrcStatus {
+    Valid(u32),
+    Mismatch { expected: u32, actual: u32 },
+    /// The file size does not match `length + 4`, so there is no trailer to check.
+    Absent,
+}
+
+/// Outcome of checking the ASCII MD5 trailer of an RKFW image.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Md5Status {
+    Valid(String),
+    Mismatch { expected: String, actual: String },
+    /// No 32 hex digits follow the embedded update image.
+    Absent,
+}
+
+/// Outcome of cross-checking the `package-file` of an RKAF image against its part table.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PackageStatus {
+    /// Every entry matches a part with data and vice versa; holds the number of entries.
+    Valid(usize),
+    Mismatch(Vec<PackageIssue>),
+    /// The `package-file` part could not be read or parsed.
+    Invalid(String),
+    /// The image has no `package-file` part.
+    Absent,
+}
+
+/// Checksums found in a firmware file. `md5` is only set for RKFW images,
+/// `crc` and `package` for RKAF images and for the update image embedded in an RKFW.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+    pub md5: Option<Md5Status>,
+    pub crc: Option<CrcStatus>,
+    pub package: Option<PackageStatus>,
+}
+
+impl VerifyReport {
+    /// True when every checksum in the report was present and matched, and
+    /// `package-file` agrees with the part table.
+    pub fn is_ok(&self) -> bool {
+        let md5_ok = !matches!(self.md5, Some(Md5Status::Mismatch { .. }) | Some(Md5Status::Absent));
+        let crc_ok = !matches!(self.crc, Some(CrcStatus::Mismatch { .. }) | Some(CrcStatus::Absent));
+        let package_ok = matches!(self.package, None | Some(PackageStatus::Valid(_)));
+        md5_ok && crc_ok && package_ok
+    }
+}
+
+/// Checks every checksum the firmware file at `file_path` carries.
+pub fn verify_file(file_path: &str) -> Result<VerifyReport> {
+    let mut fp = File::open(file_path).with_path(file_path)?;
+    verify_reader(&mut fp)
+}
+
+/// Checks every checksum of a firmware image read from any seekable source.
+pub fn verify_reader<R: Read + Seek>(fp: &mut R) -> Result<VerifyReport> {
+    let filesize = stream_len(fp)?;
+    let mut signature = [0u8; 4];
+    read_header(fp, &mut signature, filesize, "image signature")?;
+
+    let mut report = VerifyReport::default();
+    match &signature[..] {
+        RKAF_SIGNATURE => {
+            let header = read_update_header(fp, filesize)?;
+            report.crc = Some(rkaf_crc_status(fp, header.length as u64, filesize)?);
+            report.package = Some(package_status(fp, &header, filesize)?);
+        }
+        RKFP_SIGNATURE => return Err(rkfp_unsupported()),
+        RKFW_SIGNATURE => {
+            report.md5 = Some(rkfw_md5_status(fp, filesize)?);
+            let header = read_rkfw_header(fp, filesize)?;
+            let image_offset = header.update_offset as u64;
+            let image_length = header.update_size as u64;
+            if image_offset + image_length <= filesize && image_length >= 8 {
+                let mut image = SubReader::new(&mut *fp, image_offset, image_length);
+                image.read_exact(&mut signature)?;
+                if signature == RKAF_SIGNATURE {
+                    let header = read_update_header(&mut image, image_length)?;
+                    report.crc = Some(rkaf_crc_status(&mut image, header.length as u64, image_length)?);
+                    report.package = Some(package_status(&mut image, &header, image_length)?);
+                }
+            }
+        }
+        _ => return Err(Error::UnknownSignature { signature: signature.to_vec() }),
+    }
+    Ok(report)
+}
+
+/// Checks the RKCRC trailer of the RKAF image at `file_path`.
+pub fn verify_rkaf_crc(file_path: &str) -> Result<CrcStatus> {
+    let mut fp = File::open(file_path).with_path(file_path)?;
+    let filesize = fp.metadata().with_path(file_path)?.len();
+    let header = read_update_header(&mut fp, filesize)?;
+    rkaf_crc_status(&mut fp, header.length as u64, filesize)
+}
+
+/// Checks the MD5 trailer of the RKFW image at `file_path`.
+pub fn verify_rkfw_md5(file_path: &str) -> Result<Md5Status> {
+    let mut fp = File::open(file_path).with_path(file_path)?;
+    let filesize = fp.metadata().with_path(file_path)?.len();
+    let mut signature = [0u8; 4];
+    fp.read_exact(&mut signature).with_path(file_path)?;
+    if signature == RKFP_SIGNATURE {
+        return Err(rkfp_unsupported());
+    }
+    if signature != RKFW_SIGNATURE {
+        return Err(Error::BadMagic {
+            what: "RKFW header",
+            offset: 0,
+            expected: RKFW_SIGNATURE,
+            found: signature.to_vec(),
+        });
+    }
+    rkfw_md5_status(&mut fp, filesize)
+}
+
+/// Checks the RKCRC of an RKAF image of `size` bytes whose header claims `length`.
+pub(crate) fn rkaf_crc_status<R: Read + Seek>(fp: &mut R, length: u64, size: u64) -> Result<CrcStatus> {
+    if size != length + 4 {
+        return Ok(CrcStatus::Absent);
+    }
+
+    fp.seek(SeekFrom::Start(0))?;
+    let mut crc = RkCrc32::new();
+    stream_range(fp, length, |chunk| crc.update(chunk))?;
+
+    let mut trailer = [0u8; 4];
+    fp.read_exact(&mut trailer)?;
+    let expected = u32::from_le_bytes(trailer);
+    let actual = crc.finish();
+    if expected == actual {
+        Ok(CrcStatus::Valid(actual))
+    } else {
+        Ok(CrcStatus::Mismatch { expected, actual })
+    }
+}
+
+/// Reads the `package-file` part of an RKAF image of `size` bytes and checks it
+/// against the part table of `header`.
+pub(crate) fn package_status<R: Read + Seek>(fp: &mut R, header: &UpdateHeader, size: u64) -> Result<PackageStatus> {
+    let num_parts = (header.num_parts as usize).min(header.parts.len());
+    let Some(part) = header.parts[..num_parts].iter().find(|part| part.name() == "package-file") else {
+        return Ok(PackageStatus::Absent);
+    };
+    let (offset, len) = (part.part_offset as u64, part.part_byte_count as u64);
+    if offset == 0 || offset + len > size {
+        return Ok(PackageStatus::Invalid("package-file has no data in the image".to_string()));
+    }
+    if len > MAX_PACKAGE_FILE_LEN {
+        return Ok(PackageStatus::Invalid(format!("package-file part is {} bytes", len)));
+    }
+
+    let mut data = vec![0u8; len as usize];
+    fp.seek(SeekFrom::Start(offset))?;
+    fp.read_exact(&mut data)?;
+    let package = match PackageFile::from_bytes(&data) {
+        Ok(package) => package,
+        Err(e) => return Ok(PackageStatus::Invalid(e.to_string())),
+    };
+    let issues = package.check(header, size);
+    if issues.is_empty() {
+        Ok(PackageStatus::Valid(package.entries.len()))
+    } else {
+        Ok(PackageStatus::Mismatch(issues))
+    }
+}
+
+/// The MD5 trailer follows the embedded update image, which is the last part of an RKFW.
+pub(crate) fn rkfw_md5_status<R: Read + Seek>(fp: &mut R, filesize: u64) -> Result<Md5Status> {
+    let body_len = read_rkfw_header(fp, filesize)?.body_len();
+    if body_len + MD5_TRAILER_LEN > filesize {
+        return Ok(Md5Status::Absent);
+    }
+
+    let mut trailer = [0u8; MD5_TRAILER_LEN as usize];
+    fp.seek(SeekFrom::Start(body_len))?;
+    fp.read_exact(&mut trailer)?;
+    if !trailer.iter().all(u8::is_ascii_hexdigit) {
+        return Ok(Md5Status::Absent);
+    }
+    let expected = String::from_utf8_lossy(&trailer).to_ascii_lowercase();
+
+    fp.seek(SeekFrom::Start(0))?;
+    let mut md5 = md5::Context::new();
+    stream_range(fp, body_len, |chunk| md5.consume(chunk))?;
+    let actual = format!("{:x}", md5.compute());
+    if expected == actual {
+        Ok(Md5Status::Valid(actual))
+    } else {
+        Ok(Md5Status::Mismatch { expected, actual })
+    }
+}
+
+fn stream_range<R: Read>(fp: &mut R, len: u64, mut f: impl FnMut(&[u8])) -> Result<()> {
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut remaining = len;
+    while remaining > 0 {
+        let read_len = std::cmp::min(remaining as usize, buffer.len());
+        fp.read_exact(&mut buffer[..read_len])?;
+        f(&buffer[..read_len]);
+        remaining -= read_len as u64;
+    }
+    Ok(())
+}
+
+fn read_rkfw_header<R: Read + Seek>(fp: &mut R, filesize: u64) -> Result<RkfwHeader> {
+    let mut buf = [0u8; RKFW_HEADER_LEN];
+    read_header(fp, &mut buf, filesize, "RKFW header")?;
+    RkfwHeader::from_bytes(&buf)
+}
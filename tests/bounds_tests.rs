@@ -0,0 +1,108 @@
+mod common;
+
+#[cfg(test)]
+mod bounds_tests {
+    use std::fs;
+    use std::io::Cursor;
+    use afptool_rs::{
+        unpack_to_sink, verify_reader, Error, MemorySink, UnpackObserver, UnpackOptions, Warning, RKFW_SIGNATURE,
+    };
+    use tempfile::TempDir;
+    use crate::common::{create_boot_kernel_rkaf, edit_header};
+
+    #[derive(Default)]
+    struct Warnings(Vec<Warning>);
+
+    impl UnpackObserver for Warnings {
+        fn warning(&mut self, warning: &Warning) {
+            self.0.push(warning.clone());
+        }
+    }
+
+    fn unpack(data: Vec<u8>, options: &UnpackOptions) -> (Result<(), Error>, MemorySink, Vec<Warning>) {
+        let mut sink = MemorySink::new();
+        let mut warnings = Warnings::default();
+        let result = unpack_to_sink(&mut Cursor::new(data), &mut sink, &mut warnings, options);
+        (result, sink, warnings.0)
+    }
+
+    #[test]
+    fn test_short_inputs() {
+        // 少于 4 字节或头部不完整的文件返回截断错误而不是 panic
+        for data in [&b""[..], b"RK", b"RKF", b"RKFW", b"RKAF\0\0\0\0"] {
+            let (result, _, _) = unpack(data.to_vec(), &UnpackOptions::default());
+            assert!(matches!(result, Err(Error::Truncated { .. })), "{:?}", data);
+        }
+        for data in [&b""[..], b"RK", b"RKF", b"RKFW"] {
+            assert!(matches!(verify_reader(&mut Cursor::new(data)), Err(Error::Truncated { .. })));
+        }
+    }
+
+    #[test]
+    fn test_rkfw_offsets_out_of_bounds() {
+        let mut rkfw = vec![0u8; 0x100];
+        rkfw[0..4].copy_from_slice(RKFW_SIGNATURE);
+        // loader 偏移远超文件末尾，偏移加长度会溢出 u32
+        rkfw[0x19..0x1d].copy_from_slice(&0xffff_fff0u32.to_le_bytes());
+        rkfw[0x1d..0x21].copy_from_slice(&0x20u32.to_le_bytes());
+        rkfw[0x21..0x25].copy_from_slice(&0x66u32.to_le_bytes());
+        rkfw[0x25..0x29].copy_from_slice(&0xffff_ffffu32.to_le_bytes());
+
+        let (result, _, _) = unpack(rkfw.clone(), &UnpackOptions::default());
+        assert!(matches!(
+            result,
+            Err(Error::OffsetOutOfBounds { offset: 0xffff_fff0, size: 0x20, limit: 0x100, .. })
+        ));
+
+        // 抢救模式下两个部分都被跳过，并给出警告
+        let options = UnpackOptions { salvage: true, ..Default::default() };
+        let (result, sink, warnings) = unpack(rkfw, &options);
+        result.unwrap();
+        assert!(sink.files.is_empty());
+        assert_eq!(warnings.iter().filter(|w| matches!(w, Warning::PartitionSkipped { .. })).count(), 2);
+    }
+
+    #[test]
+    fn test_invalid_num_parts() {
+        let temp_dir = TempDir::new().unwrap();
+        let data = fs::read(create_boot_kernel_rkaf(temp_dir.path())).unwrap();
+
+        // 分区数超过上限时返回错误而不是越界访问
+        for num_parts in [u32::MAX, 129] {
+            let mut data = data.clone();
+            edit_header(&mut data, |header| header.num_parts = num_parts);
+            let (result, sink, _) = unpack(data, &UnpackOptions { salvage: true, ..Default::default() });
+            assert!(matches!(result, Err(Error::InvalidPartCount { count, .. }) if count == num_parts));
+            assert!(sink.files.is_empty());
+        }
+
+        // 标准头部中的分区数大于 16 时，扩展分区表会与分区数据重叠
+        let mut data = data;
+        edit_header(&mut data, |header| header.num_parts = 17);
+        let (result, _, _) = unpack(data, &UnpackOptions::default());
+        let err = result.unwrap_err();
+        assert!(matches!(err, Error::InvalidPartCount { count: 17, .. }));
+        assert!(err.to_string().contains("overlap"));
+    }
+
+    #[test]
+    fn test_salvage_rkaf_partitions() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut data = fs::read(create_boot_kernel_rkaf(temp_dir.path())).unwrap();
+        // 修改分区偏移后重新计算 CRC，只留下越界这一处错误
+        edit_header(&mut data, |header| header.parts[1].part_offset = 0x7fff_0000);
+
+        let (result, _, _) = unpack(data.clone(), &UnpackOptions::default());
+        let err = result.unwrap_err();
+        assert!(matches!(err, Error::OffsetOutOfBounds { offset: 0x7fff_0000, .. }));
+        assert!(err.to_string().contains("Image/boot.img"));
+
+        // 抢救模式跳过损坏的 boot，仍然提取 kernel
+        let options = UnpackOptions { salvage: true, ..Default::default() };
+        let (result, sink, warnings) = unpack(data, &options);
+        result.unwrap();
+        assert_eq!(sink.get("Image/kernel.img"), Some(&b"kernel"[..]));
+        assert!(sink.get("Image/boot.img").is_none());
+        assert!(matches!(&warnings[..], [Warning::PartitionSkipped { path, .. }] if path == "Image/boot.img"));
+    }
+}
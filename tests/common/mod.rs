@@ -0,0 +1,82 @@
+// 各测试文件共用的映像构造和改写工具，并非每个文件都用到全部函数
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use afptool_rs::{pack_rkafp, pack_rkfw, rkcrc32, BuildDate, ChipFamily, FirmwareVersion, UpdateHeader};
+
+// 只含 boot 分区的 package-file
+pub const BOOT_PACKAGE_FILE: &str = "package-file\tpackage-file\nboot\tImage/boot.img\n";
+// 含 boot 和 kernel 两个分区的 package-file
+pub const BOOT_KERNEL_PACKAGE_FILE: &str =
+    "package-file\tpackage-file\nboot\tImage/boot.img\nkernel\tImage/kernel.img\n";
+
+// 在 dir/src 下写入 package-file 和分区文件，打包为 dir/update.img
+pub fn pack_image(dir: &Path, package_file: &str, files: &[(&str, &[u8])]) -> PathBuf {
+    let src = dir.join("src");
+    fs::create_dir_all(src.join("Image")).unwrap();
+    fs::write(src.join("package-file"), package_file).unwrap();
+    for (path, data) in files {
+        fs::write(src.join(path), data).unwrap();
+    }
+
+    let image = dir.join("update.img");
+    pack_rkafp(src.to_str().unwrap(), image.to_str().unwrap()).unwrap();
+    image
+}
+
+// 只含 boot 分区的最小 RKAF 映像
+pub fn create_rkaf(dir: &Path, boot: &[u8]) -> PathBuf {
+    pack_image(dir, BOOT_PACKAGE_FILE, &[("Image/boot.img", boot)])
+}
+
+// 含 boot 和 kernel 两个分区的 RKAF 映像
+pub fn create_boot_kernel_rkaf(dir: &Path) -> PathBuf {
+    pack_image(
+        dir,
+        BOOT_KERNEL_PACKAGE_FILE,
+        &[("Image/boot.img", b"boot"), ("Image/kernel.img", b"kernel")],
+    )
+}
+
+// 用 loader 和 RKAF 映像生成 RKFW 映像 dir/rk.img
+pub fn create_rkfw(dir: &Path, loader: &[u8], update: &Path, chip: ChipFamily, date: BuildDate) -> PathBuf {
+    let loader_path = dir.join("loader.bin");
+    fs::write(&loader_path, loader).unwrap();
+
+    let image = dir.join("rk.img");
+    pack_rkfw(
+        loader_path.to_str().unwrap(),
+        update.to_str().unwrap(),
+        chip,
+        FirmwareVersion::default(),
+        date,
+        None,
+        image.to_str().unwrap(),
+    )
+    .unwrap();
+    image
+}
+
+// 重新计算 RKAF 映像末尾的 CRC
+pub fn update_crc(data: &mut [u8]) {
+    let length = data.len() - 4;
+    let crc = rkcrc32(&data[..length]);
+    data[length..].copy_from_slice(&crc.to_le_bytes());
+}
+
+// 解析 RKAF 头部，修改后写回并重新计算 CRC，只留下所做的改动
+pub fn edit_header(data: &mut [u8], edit: impl FnOnce(&mut UpdateHeader)) {
+    let mut header = UpdateHeader::from_bytes(data).unwrap();
+    edit(&mut header);
+    let bytes = header.to_bytes();
+    data[..bytes.len()].copy_from_slice(&bytes);
+    update_crc(data);
+}
+
+// 把分区项的 full_path 换成任意字节
+pub fn set_full_path(header: &mut UpdateHeader, index: usize, full_path: &[u8]) {
+    let field = &mut header.parts[index].full_path;
+    field.fill(0);
+    field[..full_path.len()].copy_from_slice(full_path);
+}
@@ -0,0 +1,186 @@
+#[cfg(test)]
+mod gpt_tests {
+    use std::fs;
+    use std::io::Cursor;
+    use afptool_rs::{wrap_parameter, Gpt, Parameter, PartitionTable, GPT_BACKUP_SECTORS, GPT_PRIMARY_SECTORS};
+    use assert_cmd::Command;
+    use tempfile::TempDir;
+
+    const PARAMETER: &str = "FIRMWARE_VER: 11.0\n\
+TYPE: GPT\n\
+CMDLINE: mtdparts=rk29xxnand:0x00002000@0x00004000(uboot),0x00002000@0x00006000(misc),\
+0x00020000@0x00008000(boot),0x00400000@0x00028000(rootfs),-@0x00428000(userdata:grow)\n\
+uuid:rootfs=614e0000-0000-4b53-8000-1d28000054a9\n";
+
+    // 8 GiB
+    const DISK_SIZE: u64 = 8 << 30;
+    const DISK_SECTORS: u64 = DISK_SIZE / 512;
+
+    // 小容量磁盘使用的分区表
+    const SMALL_PARAMETER: &str = "CMDLINE: mtdparts=rk29xxnand:0x00002000@0x00004000(uboot),-@0x00006000(userdata:grow)\n";
+
+    fn small_table() -> PartitionTable {
+        SMALL_PARAMETER.parse::<Parameter>().unwrap().partition_table().unwrap().unwrap()
+    }
+
+    fn table() -> PartitionTable {
+        PARAMETER.parse::<Parameter>().unwrap().partition_table().unwrap().unwrap()
+    }
+
+    // 按位计算的标准 CRC32，用于独立校验
+    fn crc32(data: &[u8]) -> u32 {
+        let mut crc = !0u32;
+        for &b in data {
+            crc ^= b as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+            }
+        }
+        !crc
+    }
+
+    fn u32_at(data: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(data[at..at + 4].try_into().unwrap())
+    }
+
+    fn u64_at(data: &[u8], at: usize) -> u64 {
+        u64::from_le_bytes(data[at..at + 8].try_into().unwrap())
+    }
+
+    // 检查 GPT 头部的各个字段和两个 CRC
+    fn check_header(header: &[u8], entries: &[u8], my_lba: u64, alternate_lba: u64, entries_lba: u64) {
+        assert_eq!(&header[0..8], b"EFI PART");
+        assert_eq!(u32_at(header, 12), 92);
+        let mut zeroed = header[..92].to_vec();
+        zeroed[16..20].fill(0);
+        assert_eq!(u32_at(header, 16), crc32(&zeroed));
+        assert_eq!(u64_at(header, 24), my_lba);
+        assert_eq!(u64_at(header, 32), alternate_lba);
+        assert_eq!(u64_at(header, 40), 34);
+        assert_eq!(u64_at(header, 48), DISK_SECTORS - 34);
+        assert_eq!(u64_at(header, 72), entries_lba);
+        assert_eq!(u32_at(header, 80), 128);
+        assert_eq!(u32_at(header, 84), 128);
+        assert_eq!(u32_at(header, 88), crc32(entries));
+    }
+
+    #[test]
+    fn test_gpt_layout() {
+        let gpt = Gpt::from_table(&table(), DISK_SIZE).unwrap();
+        assert_eq!(gpt.primary.len() as u64, GPT_PRIMARY_SECTORS * 512);
+        assert_eq!(gpt.backup.len() as u64, GPT_BACKUP_SECTORS * 512);
+        assert_eq!(gpt.backup_lba(), DISK_SECTORS - 33);
+
+        // 保护性 MBR
+        let mbr = &gpt.primary[..512];
+        assert_eq!(mbr[446 + 4], 0xee);
+        assert_eq!(u32_at(mbr, 446 + 8), 1);
+        assert_eq!(u32_at(mbr, 446 + 12), (DISK_SECTORS - 1) as u32);
+        assert_eq!(&mbr[510..], &[0x55, 0xaa]);
+
+        let entries = &gpt.primary[1024..];
+        check_header(&gpt.primary[512..1024], entries, 1, DISK_SECTORS - 1, 2);
+        check_header(&gpt.backup[16384..], &gpt.backup[..16384], DISK_SECTORS - 1, 1, DISK_SECTORS - 33);
+        assert_eq!(&gpt.backup[..16384], entries);
+
+        // 分区项：起止扇区、名称，以及 uuid 注释（前三段小端）
+        let boot = &entries[2 * 128..3 * 128];
+        assert_eq!((u64_at(boot, 32), u64_at(boot, 40)), (0x8000, 0x27fff));
+        let name: Vec<u8> = "boot".encode_utf16().flat_map(u16::to_le_bytes).collect();
+        assert_eq!(&boot[56..56 + name.len()], &name[..]);
+        let rootfs = &entries[3 * 128..4 * 128];
+        assert_eq!(
+            &rootfs[16..32],
+            &[0x00, 0x00, 0x4e, 0x61, 0x00, 0x00, 0x53, 0x4b, 0x80, 0x00, 0x1d, 0x28, 0x00, 0x00, 0x54, 0xa9]
+        );
+        // grow 分区延伸到最后一个可用扇区
+        let userdata = &entries[4 * 128..5 * 128];
+        assert_eq!((u64_at(userdata, 32), u64_at(userdata, 40)), (0x428000, DISK_SECTORS - 34));
+        assert!(entries[5 * 128..].iter().all(|&b| b == 0));
+
+        // 相同输入得到完全相同的结果，没有 uuid 的分区 GUID 也各不相同
+        assert_eq!(Gpt::from_table(&table(), DISK_SIZE).unwrap(), gpt);
+        assert_ne!(&entries[16..32], &entries[128 + 16..128 + 32]);
+    }
+
+    #[test]
+    fn test_gpt_write_disk() {
+        let gpt = Gpt::from_table(&small_table(), 64 << 20).unwrap();
+        let mut disk = Cursor::new(vec![0u8; 64 << 20]);
+        gpt.write_disk(&mut disk).unwrap();
+        let disk = disk.into_inner();
+        assert_eq!(&disk[..gpt.primary.len()], &gpt.primary[..]);
+        assert_eq!(&disk[disk.len() - gpt.backup.len()..], &gpt.backup[..]);
+    }
+
+    #[test]
+    fn test_gpt_errors() {
+        // 磁盘太小，分区起点已超出可用范围
+        assert!(Gpt::from_table(&small_table(), 64 << 20).is_ok());
+        assert!(Gpt::from_table(&table(), 64 << 20).is_err());
+        assert!(Gpt::from_table(&small_table(), 1024).is_err());
+
+        for bad in [
+            "rk29xxnand:0x00002000@0x00000010(uboot)",
+            "rk29xxnand:0x00002000@0x00004000(uboot),0x00002000@0x00005000(misc)",
+            "rk29xxnand:0x00000000@0x00004000(uboot)",
+            "rk29xxnand:0x00002000@0x00004000(a_partition_name_longer_than_36_chars)",
+        ] {
+            let table: PartitionTable = bad.parse().unwrap();
+            assert!(Gpt::from_table(&table, DISK_SIZE).is_err(), "{}", bad);
+        }
+
+        let mut table = table();
+        table.get_mut("boot").unwrap().uuid = Some("not-a-uuid".into());
+        assert!(Gpt::from_table(&table, DISK_SIZE).is_err());
+    }
+
+    #[test]
+    fn test_cli_gpt() {
+        let temp_dir = TempDir::new().unwrap();
+        let parameter = temp_dir.path().join("parameter.txt");
+        fs::write(&parameter, wrap_parameter(PARAMETER.as_bytes())).unwrap();
+        let output = temp_dir.path().join("gpt.img");
+        let backup = temp_dir.path().join("backup.img");
+
+        Command::cargo_bin("afptool-rs")
+            .unwrap()
+            .arg("gpt")
+            .arg(&parameter)
+            .arg(&output)
+            .args(["--disk-size", "8G", "--backup"])
+            .arg(&backup)
+            .assert()
+            .success();
+
+        let gpt = Gpt::from_table(&table(), DISK_SIZE).unwrap();
+        assert_eq!(fs::read(&output).unwrap(), gpt.primary);
+        assert_eq!(fs::read(&backup).unwrap(), gpt.backup);
+
+        // 可直接生成整个磁盘大小的映像
+        let small = temp_dir.path().join("small.txt");
+        fs::write(&small, SMALL_PARAMETER).unwrap();
+        let image = temp_dir.path().join("disk.img");
+        Command::cargo_bin("afptool-rs")
+            .unwrap()
+            .arg("gpt")
+            .arg(&small)
+            .arg(&image)
+            .args(["--disk-size", "64M", "--disk-image"])
+            .assert()
+            .success();
+        let disk = fs::read(&image).unwrap();
+        assert_eq!(disk.len(), 64 << 20);
+        assert_eq!(&disk[512..520], b"EFI PART");
+        assert_eq!(&disk[disk.len() - 512..disk.len() - 504], b"EFI PART");
+
+        Command::cargo_bin("afptool-rs")
+            .unwrap()
+            .arg("gpt")
+            .arg(&parameter)
+            .arg(&output)
+            .args(["--disk-size", "8X"])
+            .assert()
+            .failure();
+    }
+}
@@ -3,7 +3,8 @@ mod tests {
     use std::fs::{self, File};
     use std::io::Write;
     use std::path::Path;
-    use afptool_rs::{RKAF_SIGNATURE, RKFW_SIGNATURE, UpdateHeader};
+    use afptool_rs::{unpack_file, verify_file, verify_rkfw_md5, ChipFamily, Error, RKAF_SIGNATURE, RKFP_SIGNATURE, RKFW_SIGNATURE, RkfwHeader, UpdateHeader, UpdatePart};
+    use tempfile::TempDir;
 
     // 创建模拟的 RKFW 文件用于测试
     fn create_mock_rkfw() -> Vec<u8> {
@@ -94,7 +95,7 @@ mod tests {
     #[test]
     fn test_update_header_from_bytes() {
         let mock_rkaf = create_mock_rkaf();
-        let header = UpdateHeader::from_bytes(&mock_rkaf);
+        let header = UpdateHeader::from_bytes(&mock_rkaf).unwrap();
         
         assert_eq!(&header.magic, RKAF_SIGNATURE);
         // 使用临时变量避免 packed struct 对齐问题
@@ -144,6 +145,149 @@ mod tests {
         assert_eq!(bytes[num_parts_offset + 3], 0);
     }
 
+    #[test]
+    fn test_update_header_truncated() {
+        let mock_rkaf = create_mock_rkaf();
+        assert!(UpdateHeader::from_bytes(&mock_rkaf[..100]).is_err());
+        assert!(UpdatePart::from_bytes(&mock_rkaf[..111]).is_err());
+    }
+
+    #[test]
+    fn test_update_header_roundtrip() {
+        let mut mock_rkaf = create_mock_rkaf();
+        // 在第一个分区项中写入偏移量和大小（小端序）
+        let part_offset = 4 + 4 + 34 + 30 + 56 + 4 + 4 + 4;
+        mock_rkaf[part_offset..part_offset + 4].copy_from_slice(b"boot");
+        mock_rkaf[part_offset + 96..part_offset + 100].copy_from_slice(&[0x00, 0x08, 0x00, 0x00]);
+        mock_rkaf[part_offset + 108..part_offset + 112].copy_from_slice(&[0x34, 0x12, 0x00, 0x00]);
+
+        let header = UpdateHeader::from_bytes(&mock_rkaf).unwrap();
+        let part = header.parts[0];
+        assert_eq!(part.part_offset, 0x800);
+        assert_eq!(part.part_byte_count, 0x1234);
+
+        assert_eq!(&header.to_bytes()[..], &mock_rkaf[..]);
+        assert_eq!(UpdatePart::from_bytes(&part.to_bytes()).unwrap(), part);
+    }
+
+    #[test]
+    fn test_rkfw_header_from_bytes() {
+        let mock_rkfw = create_mock_rkfw();
+        let header = RkfwHeader::from_bytes(&mock_rkfw).unwrap();
+
+        assert_eq!(header.version.to_string(), "8.1.0");
+        assert_eq!(header.chip_family(), ChipFamily::Px30);
+        assert_eq!(header.loader_offset, 0x66);
+        assert_eq!(header.loader_size, 0x10);
+        assert_eq!(header.update_offset, 0x76);
+        assert_eq!(header.update_size, 0x20);
+
+        // 序列化后应与原始头部一致
+        assert_eq!(&header.to_bytes()[..], &mock_rkfw[..0x66]);
+    }
+
+    #[test]
+    fn test_rkfw_header_too_short() {
+        let mock_rkfw = create_mock_rkfw();
+        assert!(RkfwHeader::from_bytes(&mock_rkfw[..0x40]).is_err());
+        assert!(RkfwHeader::from_bytes(&[0u8; 0x66]).is_err());
+    }
+
+    #[test]
+    fn test_rkfp_image_rejected() {
+        // RKFP 的布局未知，能识别签名但拒绝解包和校验，而不是按 RKFW 猜测
+        let mut mock_rkfp = create_mock_rkfw();
+        mock_rkfp[0..4].copy_from_slice(RKFP_SIGNATURE);
+        assert!(matches!(RkfwHeader::from_bytes(&mock_rkfp), Err(Error::BadMagic { .. })));
+
+        let temp_dir = TempDir::new().unwrap();
+        let input = temp_dir.path().join("mock.rkfp");
+        let output = temp_dir.path().join("out");
+        File::create(&input).unwrap().write_all(&mock_rkfp).unwrap();
+        let err = unpack_file(input.to_str().unwrap(), output.to_str().unwrap()).unwrap_err();
+        assert!(matches!(err, Error::Unsupported { format: "RKFP", .. }), "{}", err);
+        assert!(!output.join("BOOT").exists());
+        assert!(matches!(verify_file(input.to_str().unwrap()), Err(Error::Unsupported { .. })));
+        assert!(matches!(verify_rkfw_md5(input.to_str().unwrap()), Err(Error::Unsupported { .. })));
+    }
+
+    #[test]
+    fn test_chip_families() {
+        // 表中每个芯片系列写入模拟 RKFW 后都应能识别，并且名称和代码可以互相转换
+        for family in ChipFamily::all() {
+            let mut mock_rkfw = create_mock_rkfw();
+            mock_rkfw[0x15..0x19].copy_from_slice(&family.code().to_le_bytes());
+            let header = RkfwHeader::from_bytes(&mock_rkfw).unwrap();
+            assert_eq!(header.chip_family(), family);
+
+            let name = family.name().unwrap();
+            assert_eq!(ChipFamily::from_name(name), Some(family));
+            assert_eq!(name.to_lowercase().parse::<ChipFamily>().unwrap(), family);
+            assert_eq!(family.to_string(), name);
+        }
+
+        // rkImageMaker 标签按字节倒序存放
+        let mut mock_rkfw = create_mock_rkfw();
+        mock_rkfw[0x15..0x19].copy_from_slice(b"C033");
+        assert_eq!(RkfwHeader::from_bytes(&mock_rkfw).unwrap().chip_family(), ChipFamily::Rk3399);
+
+        // 只写了低字节的旧映像
+        assert_eq!(ChipFamily::from_code(0x41), ChipFamily::Rk3368);
+        assert_eq!(ChipFamily::from_code(0x36), ChipFamily::Rk3326);
+        // 0x38 一直显示为 RK3566，rkImageMaker 的 3568 标签显示为 RK3568
+        assert_eq!(ChipFamily::from_code(0x38), ChipFamily::Rk3566);
+        assert_eq!(ChipFamily::from_code(0x38).name(), Some("RK3566"));
+        assert_eq!(ChipFamily::from_code(u32::from_le_bytes(*b"8653")), ChipFamily::Rk3568);
+        assert_eq!(ChipFamily::from_code(0x30), ChipFamily::Px30);
+
+        // 别名、十六进制代码和未知芯片
+        assert_eq!(ChipFamily::from_name("rk3566"), Some(ChipFamily::Rk3566));
+        assert_eq!(ChipFamily::from_name("RV1109"), Some(ChipFamily::Rv1126));
+        assert_eq!(ChipFamily::from_name("0x50"), Some(ChipFamily::Rk29xx));
+        assert_eq!(ChipFamily::from_name("0x99"), Some(ChipFamily::Unknown(0x99)));
+        assert_eq!(ChipFamily::from_code(0x99).name(), None);
+        assert_eq!(ChipFamily::Unknown(0x99).code(), 0x99);
+        assert!("RK9999".parse::<ChipFamily>().is_err());
+    }
+
+    #[test]
+    fn test_typed_errors() {
+        let mock_rkaf = create_mock_rkaf();
+        let err = UpdateHeader::from_bytes(&mock_rkaf[..100]).unwrap_err();
+        assert!(matches!(err, Error::Truncated { needed: 2048, available: 100, .. }));
+
+        let temp_dir = TempDir::new().unwrap();
+        let output = temp_dir.path().join("out");
+
+        // 未知签名
+        let unknown = temp_dir.path().join("unknown.bin");
+        File::create(&unknown).unwrap().write_all(b"ABCD0000").unwrap();
+        let err = unpack_file(unknown.to_str().unwrap(), output.to_str().unwrap()).unwrap_err();
+        assert!(matches!(err, Error::UnknownSignature { .. }));
+
+        // 嵌入的更新映像不是 RKAF 时返回错误而不是 panic
+        let mut rkfw = create_mock_rkfw();
+        rkfw[0x76..0x7a].copy_from_slice(b"XXXX");
+        let bad = temp_dir.path().join("bad.rkfw");
+        File::create(&bad).unwrap().write_all(&rkfw).unwrap();
+        let err = unpack_file(bad.to_str().unwrap(), output.to_str().unwrap()).unwrap_err();
+        assert!(matches!(err, Error::BadMagic { offset: 0x76, .. }));
+
+        // BOOT 超出文件末尾时按流式读取返回截断错误
+        let mut rkfw = create_mock_rkfw();
+        rkfw[0x1d..0x21].copy_from_slice(&0x10000u32.to_le_bytes());
+        let truncated = temp_dir.path().join("truncated.rkfw");
+        File::create(&truncated).unwrap().write_all(&rkfw).unwrap();
+        let err = unpack_file(truncated.to_str().unwrap(), output.to_str().unwrap()).unwrap_err();
+        assert!(matches!(err, Error::Truncated { offset: 0x66, .. }));
+
+        // 文件不存在时错误信息带路径
+        let missing = temp_dir.path().join("missing.img");
+        let err = unpack_file(missing.to_str().unwrap(), output.to_str().unwrap()).unwrap_err();
+        assert!(matches!(err, Error::File { .. }));
+        assert!(err.to_string().contains("missing.img"));
+    }
+
     #[test]
     fn test_create_mock_files() {
         // 创建测试目录
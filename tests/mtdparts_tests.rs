@@ -0,0 +1,119 @@
+mod common;
+
+#[cfg(test)]
+mod mtdparts_tests {
+    use std::fs;
+    use afptool_rs::{MtdPartition, Parameter, PartitionTable, UpdateHeader};
+    use tempfile::TempDir;
+    use crate::common::pack_image;
+
+    const MTDPARTS: &str = "mtdparts=rk29xxnand:0x00002000@0x00004000(uboot),0x00002000@0x00006000(misc),\
+0x00020000@0x00008000(boot),-@0x00028000(userdata:grow)";
+
+    #[test]
+    fn test_parse_mtdparts() {
+        let table: PartitionTable = MTDPARTS.parse().unwrap();
+        assert_eq!(table.device, "rk29xxnand");
+        assert_eq!(table.partitions.len(), 4);
+        assert_eq!(
+            table.partitions[0],
+            MtdPartition { name: "uboot".into(), start: 0x4000, size: Some(0x2000), grow: false, uuid: None }
+        );
+        let userdata = table.get("userdata").unwrap();
+        assert_eq!((userdata.start, userdata.size, userdata.grow), (0x28000, None, true));
+
+        // 序列化后与原字符串完全相同
+        assert_eq!(table.to_string(), MTDPARTS);
+        assert_eq!(MTDPARTS["mtdparts=".len()..].parse::<PartitionTable>().unwrap(), table);
+    }
+
+    #[test]
+    fn test_mtdparts_errors() {
+        for bad in [
+            "rk29xxnand",
+            "rk29xxnand:0x2000@0x4000",
+            "rk29xxnand:0x2000(uboot)",
+            "rk29xxnand:0x2000@0x4000(uboot",
+            "rk29xxnand:2000@0x4000(uboot)",
+            "rk29xxnand:0x2000@0x4000()",
+            "rk29xxnand:-@0x4000(userdata:ro)",
+        ] {
+            assert!(bad.parse::<PartitionTable>().is_err(), "{}", bad);
+        }
+        assert!(PartitionTable::from_cmdline("console=ttyFIQ0 root=PARTUUID=xyz").is_err());
+    }
+
+    #[test]
+    fn test_partition_table_from_parameter() {
+        let text = format!(
+            "FIRMWARE_VER: 11.0\nCMDLINE: console=ttyFIQ0 {} rootwait\nuuid:boot=614e0000-0000-4b53-8000-1d28000054a9\n",
+            MTDPARTS
+        );
+        let parameter: Parameter = text.parse().unwrap();
+        let table = parameter.partition_table().unwrap().unwrap();
+        assert_eq!(table.get("boot").unwrap().uuid.as_deref(), Some("614e0000-0000-4b53-8000-1d28000054a9"));
+        assert_eq!(table.get("uboot").unwrap().uuid, None);
+
+        // uuid 指向不存在的分区时报错
+        let parameter: Parameter = format!("CMDLINE: {}\nuuid:rootfs=1234\n", MTDPARTS).parse().unwrap();
+        assert!(parameter.partition_table().is_err());
+
+        // 没有 mtdparts 时返回 None
+        let parameter: Parameter = "CMDLINE: console=ttyFIQ0".parse().unwrap();
+        assert_eq!(parameter.partition_table().unwrap(), None);
+    }
+
+    #[test]
+    fn test_link_update_parts() {
+        let temp_dir = TempDir::new().unwrap();
+        let parameter = format!("CMDLINE: {}\n", MTDPARTS);
+        let image = pack_image(
+            temp_dir.path(),
+            "package-file\tpackage-file\nparameter\tImage/parameter.txt\nboot\tImage/boot.img\n",
+            &[("Image/parameter.txt", parameter.as_bytes()), ("Image/boot.img", b"boot")],
+        );
+        let header = UpdateHeader::from_bytes(&fs::read(&image).unwrap()).unwrap();
+
+        // 按名称找到 RKAF 中对应的分区项
+        let table: PartitionTable = MTDPARTS.parse().unwrap();
+        let boot = table.get("boot").unwrap().update_part(&header).unwrap();
+        assert_eq!(boot.name(), "boot");
+        assert_eq!(boot.part_byte_count, 4);
+        assert!(table.get("misc").unwrap().update_part(&header).is_none());
+    }
+
+    #[test]
+    fn test_edit_partition_table() {
+        let mut table: PartitionTable = MTDPARTS.parse().unwrap();
+
+        // 调整大小时后面的分区随之移动
+        table.resize("misc", Some(0x1000)).unwrap();
+        let starts: Vec<u64> = table.partitions.iter().map(|p| p.start).collect();
+        assert_eq!(starts, [0x4000, 0x6000, 0x7000, 0x27000]);
+        assert_eq!(table.get("misc").unwrap().end(), Some(0x7000));
+
+        // 最后一个分区可以在固定大小和 grow 之间切换
+        table.resize("userdata", Some(0x100000)).unwrap();
+        assert!(!table.get("userdata").unwrap().grow);
+        table.resize("userdata", None).unwrap();
+        assert!(table.get("userdata").unwrap().grow);
+        assert!(table.resize("boot", None).is_err());
+        assert!(table.resize("system", Some(0x1000)).is_err());
+
+        // 新分区按起始扇区插入，名称重复或范围重叠时报错
+        let oem: MtdPartition = "0x00001000@0x00000000(oem)".parse().unwrap();
+        table.add(oem.clone()).unwrap();
+        assert_eq!(table.partitions[0].name, "oem");
+        assert!(table.add(oem).is_err());
+        assert!(table.add("0x00001000@0x00006800(extra)".parse().unwrap()).is_err());
+        assert!(table.add("0x00001000@0x00100000(extra)".parse().unwrap()).is_err());
+
+        assert_eq!(table.remove("oem").unwrap().name, "oem");
+        assert!(table.remove("oem").is_none());
+        assert_eq!(
+            table.to_string(),
+            "mtdparts=rk29xxnand:0x00002000@0x00004000(uboot),0x00001000@0x00006000(misc),\
+0x00020000@0x00007000(boot),-@0x00027000(userdata:grow)"
+        );
+    }
+}
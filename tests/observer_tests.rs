@@ -0,0 +1,115 @@
+mod common;
+
+#[cfg(test)]
+mod observer_tests {
+    use std::fs;
+    use std::io::Cursor;
+    use afptool_rs::{
+        unpack_to_sink, Error, ImageHeader, ImageKind, MemorySink, Partition, UnpackObserver, UnpackOptions, Warning,
+    };
+    use tempfile::TempDir;
+    use crate::common::create_rkaf;
+
+    // 记录收到的所有事件
+    #[derive(Default)]
+    struct Recorder {
+        events: Vec<String>,
+        progress: Vec<(String, u64)>,
+        // 是否要求在非严格模式下也校验 CRC
+        checksums: bool,
+    }
+
+    impl UnpackObserver for Recorder {
+        fn wants_checksums(&self) -> bool {
+            self.checksums
+        }
+
+        fn header_parsed(&mut self, header: &ImageHeader<'_>) {
+            match header {
+                ImageHeader::Rkfw { .. } => self.events.push("header rkfw".to_string()),
+                ImageHeader::Rkaf { header, crc, .. } => {
+                    assert_eq!(crc.is_some(), self.checksums);
+                    self.events.push(format!("header rkaf {}", header.model_name()))
+                }
+            }
+        }
+
+        fn partition_started(&mut self, partition: &Partition) {
+            assert_eq!(partition.image, ImageKind::Rkaf);
+            self.events.push(format!("start {} {}", partition.path, partition.len));
+        }
+
+        fn bytes_written(&mut self, partition: &Partition, written: u64) {
+            self.progress.push((partition.path.clone(), written));
+        }
+
+        fn partition_finished(&mut self, partition: &Partition) {
+            self.events.push(format!("finish {}", partition.path));
+        }
+
+        fn warning(&mut self, warning: &Warning) {
+            self.events.push(format!("warning {}", warning));
+        }
+    }
+
+    #[test]
+    fn test_observer_events() {
+        let temp_dir = TempDir::new().unwrap();
+        let data = fs::read(create_rkaf(temp_dir.path(), &[0x5au8; 40000])).unwrap();
+
+        let mut recorder = Recorder::default();
+        let mut sink = MemorySink::new();
+        unpack_to_sink(&mut Cursor::new(data), &mut sink, &mut recorder, &UnpackOptions::default()).unwrap();
+
+        // 没有 parameter 时型号为空
+        let package_len = sink.get("package-file").unwrap().len();
+        assert_eq!(
+            recorder.events,
+            vec![
+                "header rkaf ".to_string(),
+                format!("start package-file {}", package_len),
+                "finish package-file".to_string(),
+                "start Image/boot.img 40000".to_string(),
+                "finish Image/boot.img".to_string(),
+            ]
+        );
+
+        // 进度单调递增，最后一次等于分区大小
+        let boot: Vec<u64> = recorder
+            .progress
+            .iter()
+            .filter(|(path, _)| path == "Image/boot.img")
+            .map(|(_, written)| *written)
+            .collect();
+        assert!(boot.len() > 1);
+        assert!(boot.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(boot.last(), Some(&40000));
+    }
+
+    #[test]
+    fn test_observer_warning() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut data = fs::read(create_rkaf(temp_dir.path(), &[0x5au8; 40000])).unwrap();
+        // 破坏 CRC，非严格模式下只发出警告
+        let len = data.len();
+        data[len - 1] ^= 0xff;
+
+        let mut recorder = Recorder { checksums: true, ..Recorder::default() };
+        let mut sink = MemorySink::new();
+        unpack_to_sink(&mut Cursor::new(data.clone()), &mut sink, &mut recorder, &UnpackOptions::default()).unwrap();
+        assert!(recorder.events[1].starts_with("warning CRC mismatch"));
+        assert_eq!(sink.files.len(), 2);
+
+        // 观察者不要求校验时不读取 CRC，也就没有警告
+        let mut recorder = Recorder::default();
+        let mut sink = MemorySink::new();
+        unpack_to_sink(&mut Cursor::new(data.clone()), &mut sink, &mut recorder, &UnpackOptions::default()).unwrap();
+        assert!(!recorder.events.iter().any(|event| event.starts_with("warning")));
+        assert_eq!(sink.files.len(), 2);
+
+        // 严格模式总会校验
+        let options = UnpackOptions { strict: true, ..UnpackOptions::default() };
+        let result = unpack_to_sink(&mut Cursor::new(data), &mut MemorySink::new(), &mut (), &options);
+        assert!(matches!(result, Err(Error::ChecksumMismatch { what: "RKAF CRC", .. })));
+    }
+}
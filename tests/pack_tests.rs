@@ -0,0 +1,360 @@
+#[cfg(test)]
+mod pack_tests {
+    use std::fs;
+    use std::path::Path;
+    use afptool_rs::{
+        pack_rkafp, pack_rkfw, rkcrc32, unpack_file, unpack_file_with_options, BuildDate, ChipFamily,
+        Error, FirmwareVersion, ImageKind, UnpackOptions, RkfwHeader, UpdateHeader, RKAF_SIGNATURE, RKFW_HEADER_LEN, RKFW_SIGNATURE,
+    };
+    use std::time::{Duration, UNIX_EPOCH};
+    use tempfile::TempDir;
+
+    const PARAMETER: &str = "FIRMWARE_VER: 8.1.0\n\
+MACHINE_MODEL: RK3326\n\
+MACHINE_ID: 007\n\
+MANUFACTURER: RK3326\n\
+MAGIC: 0x5041524B\n\
+CMDLINE: mtdparts=rk29xxnand:0x00002000@0x00004000(uboot),0x00010000@0x00008000(boot),-@0x00018000(userdata:grow)\n";
+
+    // 创建一个与解包结果相同布局的目录
+    fn create_pack_dir(dir: &Path) -> Vec<u8> {
+        fs::create_dir_all(dir.join("Image")).unwrap();
+        fs::write(
+            dir.join("package-file"),
+            "# NAME\tRelative path\n\
+package-file\tpackage-file\n\
+parameter\tImage/parameter.txt\n\
+boot\tImage/boot.img\n\
+backup\tRESERVED\n",
+        )
+        .unwrap();
+        fs::write(dir.join("Image/parameter.txt"), PARAMETER).unwrap();
+
+        let boot: Vec<u8> = (0..3000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(dir.join("Image/boot.img"), &boot).unwrap();
+        boot
+    }
+
+    #[test]
+    fn test_pack_rkafp_header() {
+        let temp_dir = TempDir::new().unwrap();
+        let src = temp_dir.path().join("src");
+        create_pack_dir(&src);
+        let image = temp_dir.path().join("update.img");
+
+        let written = pack_rkafp(src.to_str().unwrap(), image.to_str().unwrap()).unwrap();
+
+        let data = fs::read(&image).unwrap();
+        let header = UpdateHeader::from_bytes(&data).unwrap();
+        assert_eq!(&header.magic, RKAF_SIGNATURE);
+
+        // 返回写入的分区列表，RESERVED 项没有数据，不在其中
+        let paths: Vec<&str> = written.iter().map(|p| p.path.as_str()).collect();
+        assert_eq!(paths, ["package-file", "Image/parameter.txt", "Image/boot.img"]);
+        assert!(written.iter().all(|p| p.image == ImageKind::Rkaf));
+        assert_eq!((written[2].offset, written[2].len), (header.parts[2].part_offset as u64, 3000));
+        assert_eq!(&header.model[..7], b"RK3326\0");
+        assert_eq!(&header.manufacturer[..7], b"RK3326\0");
+        let num_parts = header.num_parts;
+        assert_eq!(num_parts, 4);
+
+        // 长度不包含末尾的 4 字节 CRC
+        let length = header.length as usize;
+        assert_eq!(length + 4, data.len());
+        let crc = u32::from_le_bytes(data[length..].try_into().unwrap());
+        assert_eq!(crc, rkcrc32(&data[..length]));
+
+        // 每个分区按 2048 字节对齐
+        let boot = header.parts[2];
+        let (offset, count) = (boot.part_offset, boot.part_byte_count);
+        assert_eq!(offset % 2048, 0);
+        assert_eq!(count, 3000);
+
+        // parameter 需要带 PARM 头
+        let parameter = header.parts[1];
+        let offset = parameter.part_offset as usize;
+        assert_eq!(&data[offset..offset + 4], b"PARM");
+    }
+
+    #[test]
+    fn test_pack_unpack_roundtrip() {
+        let temp_dir = TempDir::new().unwrap();
+        let src = temp_dir.path().join("src");
+        let boot = create_pack_dir(&src);
+        let image = temp_dir.path().join("update.img");
+        let out = temp_dir.path().join("out");
+
+        pack_rkafp(src.to_str().unwrap(), image.to_str().unwrap()).unwrap();
+        unpack_file(image.to_str().unwrap(), out.to_str().unwrap()).unwrap();
+
+        assert_eq!(fs::read(out.join("Image/boot.img")).unwrap(), boot);
+        assert_eq!(
+            fs::read(out.join("package-file")).unwrap(),
+            fs::read(src.join("package-file")).unwrap()
+        );
+
+        // 再次打包解包后的目录，结果应完全相同
+        let repacked = temp_dir.path().join("repacked.img");
+        pack_rkafp(out.to_str().unwrap(), repacked.to_str().unwrap()).unwrap();
+        assert_eq!(fs::read(&image).unwrap(), fs::read(&repacked).unwrap());
+    }
+
+    #[test]
+    fn test_pack_part_limit() {
+        let temp_dir = TempDir::new().unwrap();
+        let src = temp_dir.path().join("src");
+        fs::create_dir_all(src.join("Image")).unwrap();
+        let image = temp_dir.path().join("update.img");
+
+        // package-file 自身也占一个分区项，15 个文件正好填满 16 个槽位
+        let mut package_file = String::from("package-file\tpackage-file\n");
+        for i in 0..15 {
+            package_file += &format!("part{i}\tImage/part{i}.img\n");
+            fs::write(src.join(format!("Image/part{i}.img")), format!("data {i}")).unwrap();
+        }
+        fs::write(src.join("package-file"), &package_file).unwrap();
+        pack_rkafp(src.to_str().unwrap(), image.to_str().unwrap()).unwrap();
+
+        let data = fs::read(&image).unwrap();
+        let header = UpdateHeader::from_bytes(&data).unwrap();
+        let num_parts = header.num_parts;
+        assert_eq!(num_parts, 16);
+        assert_eq!(header.parts[0].part_offset, 2048);
+
+        let out = temp_dir.path().join("out");
+        unpack_file(image.to_str().unwrap(), out.to_str().unwrap()).unwrap();
+        for i in 0..15 {
+            assert_eq!(fs::read(out.join(format!("Image/part{i}.img"))).unwrap(), format!("data {i}").as_bytes());
+        }
+
+        // 第 17 项没有标准的存放位置，打包应报错且不写出映像
+        fs::remove_file(&image).unwrap();
+        package_file += "part15\tImage/part15.img\n";
+        fs::write(src.join("Image/part15.img"), "data 15").unwrap();
+        fs::write(src.join("package-file"), &package_file).unwrap();
+        let err = pack_rkafp(src.to_str().unwrap(), image.to_str().unwrap()).unwrap_err();
+        assert!(matches!(err, Error::Invalid(_)), "{err}");
+        assert!(!image.exists());
+    }
+
+    #[test]
+    fn test_pack_missing_package_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let image = temp_dir.path().join("update.img");
+        let result = pack_rkafp(temp_dir.path().to_str().unwrap(), image.to_str().unwrap());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_pack_rkfw_roundtrip() {
+        let temp_dir = TempDir::new().unwrap();
+        let src = temp_dir.path().join("src");
+        create_pack_dir(&src);
+        let update = temp_dir.path().join("update.img");
+        pack_rkafp(src.to_str().unwrap(), update.to_str().unwrap()).unwrap();
+
+        let loader = temp_dir.path().join("MiniLoaderAll.bin");
+        fs::write(&loader, b"BOOT loader body").unwrap();
+
+        let version: FirmwareVersion = "8.1.0".parse().unwrap();
+        let date: BuildDate = "2024-03-05 10:20:30".parse().unwrap();
+        let image = temp_dir.path().join("rk.img");
+        let written = pack_rkfw(
+            loader.to_str().unwrap(),
+            update.to_str().unwrap(),
+            ChipFamily::Px30,
+            version,
+            date,
+            None,
+            image.to_str().unwrap(),
+        )
+        .unwrap();
+
+        let data = fs::read(&image).unwrap();
+        assert_eq!(&data[0..4], RKFW_SIGNATURE);
+        let update_len = fs::metadata(&update).unwrap().len();
+        let parts: Vec<(&str, u64, u64)> = written.iter().map(|p| (p.path.as_str(), p.offset, p.len)).collect();
+        assert_eq!(parts, [("BOOT", 0x66, 16), ("embedded-update.img", 0x66 + 16, update_len)]);
+        assert_eq!(&data[6..10], &[0, 0, 1, 8]);
+        assert_eq!(&data[0x0e..0x15], &[0xe8, 0x07, 3, 5, 10, 20, 30]);
+        assert_eq!(data[0x15], 0x30);
+
+        // 末尾 32 字节是前面所有内容的 MD5
+        let (body, trailer) = data.split_at(data.len() - 32);
+        assert_eq!(trailer, format!("{:x}", md5::compute(body)).as_bytes());
+
+        let out = temp_dir.path().join("out");
+        unpack_file(image.to_str().unwrap(), out.to_str().unwrap()).unwrap();
+        assert_eq!(fs::read(out.join("BOOT")).unwrap(), fs::read(&loader).unwrap());
+        assert_eq!(
+            fs::read(out.join("embedded-update.img")).unwrap(),
+            fs::read(&update).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_pack_rkfw_reference_header() {
+        let temp_dir = TempDir::new().unwrap();
+        let src = temp_dir.path().join("src");
+        create_pack_dir(&src);
+        let update = temp_dir.path().join("update.img");
+        pack_rkafp(src.to_str().unwrap(), update.to_str().unwrap()).unwrap();
+        let loader = temp_dir.path().join("loader.bin");
+        fs::write(&loader, b"BOOT").unwrap();
+
+        let date: BuildDate = "2024-03-05 10:20:30".parse().unwrap();
+        let pack = |reference: Option<&RkfwHeader>, name: &str| {
+            let image = temp_dir.path().join(name);
+            let (loader, update) = (loader.to_str().unwrap(), update.to_str().unwrap());
+            pack_rkfw(loader, update, ChipFamily::Px30, FirmwareVersion::default(), date, reference, image.to_str().unwrap())
+                .unwrap();
+            fs::read(image).unwrap()
+        };
+
+        // 没有参考头部时含义未知的字节全为 0
+        let plain = pack(None, "plain.img");
+        assert!(plain[0x29..RKFW_HEADER_LEN].iter().all(|&b| b == 0));
+
+        // 有参考头部时只照搬这些字节，其余字段仍取自参数
+        let mut reference = RkfwHeader::from_bytes(&plain).unwrap();
+        for (i, b) in reference.unknown.iter_mut().enumerate() {
+            *b = (i as u8).wrapping_mul(7) | 1;
+        }
+        reference.chip = ChipFamily::Rk3399.code();
+        let data = pack(Some(&reference), "rk.img");
+        let header = RkfwHeader::from_bytes(&data).unwrap();
+        assert_eq!(header.unknown, reference.unknown);
+        assert_eq!(header.chip_family(), ChipFamily::Px30);
+        let body = RKFW_HEADER_LEN..data.len() - 32;
+        assert_eq!(data[body.clone()], plain[body]);
+
+        // 以打包结果本身为参考再打包一次，得到完全相同的映像
+        assert_eq!(pack(Some(&header), "repacked.img"), data);
+    }
+
+    #[test]
+    fn test_pack_rkfw_rejects_non_rkaf_update() {
+        let temp_dir = TempDir::new().unwrap();
+        let loader = temp_dir.path().join("loader.bin");
+        let update = temp_dir.path().join("update.img");
+        fs::write(&loader, b"BOOT").unwrap();
+        fs::write(&update, b"not an update image").unwrap();
+
+        let result = pack_rkfw(
+            loader.to_str().unwrap(),
+            update.to_str().unwrap(),
+            ChipFamily::Px30,
+            FirmwareVersion::default(),
+            BuildDate::default(),
+            None,
+            temp_dir.path().join("rk.img").to_str().unwrap(),
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_build_date_and_version_parsing() {
+        let date: BuildDate = "2024-03-05T10:20:30".parse().unwrap();
+        assert_eq!(date.to_string(), "2024-03-05 10:20:30");
+        assert!("2024-13-05".parse::<BuildDate>().is_err());
+        // 日期按月份天数和闰年校验
+        assert!("2024-02-31".parse::<BuildDate>().is_err());
+        assert!("2024-04-31".parse::<BuildDate>().is_err());
+        assert!("2023-02-29".parse::<BuildDate>().is_err());
+        assert!("2100-02-29".parse::<BuildDate>().is_err());
+        assert!("2024-02-29".parse::<BuildDate>().is_ok());
+        assert!("2000-02-29".parse::<BuildDate>().is_ok());
+        let impossible = BuildDate { year: 2024, month: 2, day: 30, ..Default::default() };
+        assert!(!impossible.is_valid());
+        assert_eq!(impossible.to_system_time(), None);
+        assert_eq!(BuildDate::from_unix(0).to_string(), "1970-01-01 00:00:00");
+        assert_eq!(BuildDate::from_unix(951_782_400).to_string(), "2000-02-29 00:00:00");
+        assert_eq!(
+            BuildDate::from_unix(1_709_634_030).to_system_time(),
+            Some(UNIX_EPOCH + Duration::from_secs(1_709_634_030))
+        );
+        assert_eq!(BuildDate::default().to_system_time(), None);
+
+        let version: FirmwareVersion = "8.1.0".parse().unwrap();
+        assert_eq!(version.to_u32(), 0x0801_0000);
+        assert_eq!(FirmwareVersion::from_u32(0x0801_0000), version);
+        assert!("8.1".parse::<FirmwareVersion>().is_err());
+    }
+
+    #[test]
+    fn test_unpack_rkfw_sets_mtime() {
+        let temp_dir = TempDir::new().unwrap();
+        let src = temp_dir.path().join("src");
+        create_pack_dir(&src);
+        let update = temp_dir.path().join("update.img");
+        pack_rkafp(src.to_str().unwrap(), update.to_str().unwrap()).unwrap();
+        let loader = temp_dir.path().join("loader.bin");
+        fs::write(&loader, b"BOOT").unwrap();
+
+        let date: BuildDate = "2024-03-05 10:20:30".parse().unwrap();
+        let image = temp_dir.path().join("rk.img");
+        pack_rkfw(
+            loader.to_str().unwrap(),
+            update.to_str().unwrap(),
+            ChipFamily::Px30,
+            FirmwareVersion::default(),
+            date,
+            None,
+            image.to_str().unwrap(),
+        )
+        .unwrap();
+
+        let out = temp_dir.path().join("out");
+        let options = UnpackOptions { set_mtime: true, ..Default::default() };
+        unpack_file_with_options(image.to_str().unwrap(), out.to_str().unwrap(), &options).unwrap();
+
+        // 解包出的文件修改时间应为固件的编译时间
+        let mtime = fs::metadata(out.join("BOOT")).unwrap().modified().unwrap();
+        assert_eq!(Some(mtime), date.to_system_time());
+    }
+
+    #[test]
+    fn test_unpack_rkfw_recursive() {
+        let temp_dir = TempDir::new().unwrap();
+        let src = temp_dir.path().join("src");
+        let boot = create_pack_dir(&src);
+        let update = temp_dir.path().join("update.img");
+        pack_rkafp(src.to_str().unwrap(), update.to_str().unwrap()).unwrap();
+        let loader = temp_dir.path().join("loader.bin");
+        fs::write(&loader, b"BOOT").unwrap();
+
+        let date: BuildDate = "2024-03-05 10:20:30".parse().unwrap();
+        let image = temp_dir.path().join("rk.img");
+        pack_rkfw(
+            loader.to_str().unwrap(),
+            update.to_str().unwrap(),
+            ChipFamily::Px30,
+            FirmwareVersion::default(),
+            date,
+            None,
+            image.to_str().unwrap(),
+        )
+        .unwrap();
+
+        // 一次解包即可得到 Image/ 下的分区，不生成中间文件
+        let out = temp_dir.path().join("out");
+        let options = UnpackOptions { recursive: true, set_mtime: true, ..Default::default() };
+        unpack_file_with_options(image.to_str().unwrap(), out.to_str().unwrap(), &options).unwrap();
+        assert_eq!(fs::read(out.join("BOOT")).unwrap(), b"BOOT");
+        assert_eq!(fs::read(out.join("Image/boot.img")).unwrap(), boot);
+        assert_eq!(
+            fs::read(out.join("package-file")).unwrap(),
+            fs::read(src.join("package-file")).unwrap()
+        );
+        assert!(!out.join("embedded-update.img").exists());
+        let mtime = fs::metadata(out.join("Image/boot.img")).unwrap().modified().unwrap();
+        assert_eq!(Some(mtime), date.to_system_time());
+
+        // 需要时仍可保留中间的 embedded-update.img
+        let out = temp_dir.path().join("keep");
+        let options = UnpackOptions { recursive: true, keep_embedded: true, ..Default::default() };
+        unpack_file_with_options(image.to_str().unwrap(), out.to_str().unwrap(), &options).unwrap();
+        assert_eq!(fs::read(out.join("embedded-update.img")).unwrap(), fs::read(&update).unwrap());
+        assert_eq!(fs::read(out.join("Image/boot.img")).unwrap(), boot);
+    }
+}
@@ -0,0 +1,132 @@
+mod common;
+
+#[cfg(test)]
+mod package_tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use afptool_rs::{verify_file, PackageEntry, PackageFile, PackageIssue, PackageStatus, UpdateHeader};
+    use assert_cmd::Command;
+    use predicates::prelude::*;
+    use tempfile::TempDir;
+    use crate::common::pack_image;
+
+    const PACKAGE_FILE: &str = "# NAME\tRelative path\n\
+package-file\tpackage-file\n\
+bootloader\tImage/MiniLoaderAll.bin\n\
+boot\tImage/boot.img   # kernel and dtb\n\
+\n\
+backup\tRESERVED\n";
+
+    // 按 PACKAGE_FILE 打包一个 RKAF 映像
+    fn create_image(dir: &Path) -> PathBuf {
+        pack_image(
+            dir,
+            PACKAGE_FILE,
+            &[("Image/MiniLoaderAll.bin", &[0xa5u8; 3000]), ("Image/boot.img", &[0x5au8; 5000])],
+        )
+    }
+
+    #[test]
+    fn test_parse_package_file() {
+        let package: PackageFile = PACKAGE_FILE.parse().unwrap();
+        assert_eq!(package.entries.len(), 4);
+        assert_eq!(
+            package.get("boot"),
+            Some(&PackageEntry { name: "boot".into(), path: "Image/boot.img".into() })
+        );
+        assert!(package.get("backup").unwrap().is_reserved());
+        assert!(!package.get("boot").unwrap().is_reserved());
+        assert!("update.img\tSELF".parse::<PackageFile>().unwrap().entries[0].is_self());
+        assert_eq!(PackageFile::from_bytes(PACKAGE_FILE.as_bytes()).unwrap(), package);
+
+        // 每行必须正好是名称和路径两项
+        let err = "boot\tImage/boot.img\nkernel\n".parse::<PackageFile>().unwrap_err();
+        assert!(err.to_string().contains("line 2"));
+        assert!("boot Image/boot.img extra".parse::<PackageFile>().is_err());
+        assert!(PackageFile::from_bytes(b"boot\tImage/\xff.img").is_err());
+    }
+
+    #[test]
+    fn test_check_package_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let data = fs::read(create_image(temp_dir.path())).unwrap();
+        let header = UpdateHeader::from_bytes(&data).unwrap();
+        let image_len = data.len() as u64;
+
+        let package: PackageFile = PACKAGE_FILE.parse().unwrap();
+        assert_eq!(package.check(&header, image_len), []);
+
+        // 路径不同、名称不同、只在一方出现
+        let mut changed = package.clone();
+        changed.entries[1].path = "Image/loader.bin".into();
+        changed.entries[2].name = "kernel".into();
+        changed.entries.remove(3);
+        changed.entries.push(PackageEntry { name: "misc".into(), path: "Image/misc.img".into() });
+        assert_eq!(
+            changed.check(&header, image_len),
+            [
+                PackageIssue::PathMismatch {
+                    name: "bootloader".into(),
+                    package_path: "Image/loader.bin".into(),
+                    image_path: "Image/MiniLoaderAll.bin".into(),
+                },
+                PackageIssue::NameMismatch {
+                    path: "Image/boot.img".into(),
+                    package_name: "kernel".into(),
+                    image_name: "boot".into(),
+                },
+                PackageIssue::OnlyInPackage { name: "misc".into(), path: "Image/misc.img".into() },
+                PackageIssue::OnlyInImage { name: "backup".into(), path: "RESERVED".into() },
+            ]
+        );
+
+        // 映像被截断后分区数据缺失，RESERVED 项不受影响
+        let boot = header.parts[2].part_offset as u64;
+        assert_eq!(
+            package.check(&header, boot + 100),
+            [PackageIssue::MissingData { name: "boot".into(), path: "Image/boot.img".into() }]
+        );
+    }
+
+    #[test]
+    fn test_verify_package_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let image = create_image(temp_dir.path());
+        let report = verify_file(image.to_str().unwrap()).unwrap();
+        assert_eq!(report.package, Some(PackageStatus::Valid(4)));
+        assert!(report.is_ok());
+
+        // 改写映像中 package-file 的内容，使 boot 的名称与分区表不一致
+        let mut data = fs::read(&image).unwrap();
+        let header = UpdateHeader::from_bytes(&data).unwrap();
+        let offset = header.parts[0].part_offset as usize;
+        let len = header.parts[0].part_byte_count as usize;
+        let text = String::from_utf8(data[offset..offset + len].to_vec()).unwrap();
+        let text = text.replace("boot\tImage", "bolt\tImage");
+        data[offset..offset + len].copy_from_slice(text.as_bytes());
+        fs::write(&image, &data).unwrap();
+
+        let report = verify_file(image.to_str().unwrap()).unwrap();
+        assert!(!report.is_ok());
+        assert_eq!(
+            report.package,
+            Some(PackageStatus::Mismatch(vec![PackageIssue::NameMismatch {
+                path: "Image/boot.img".into(),
+                package_name: "bolt".into(),
+                image_name: "boot".into(),
+            }]))
+        );
+
+        let mut cmd = Command::cargo_bin("afptool-rs").unwrap();
+        cmd.arg("verify").arg(&image);
+        cmd.assert()
+            .failure()
+            .stdout(predicate::str::contains("package-file: Image/boot.img: package-file calls it bolt"));
+
+        // 没有 package-file 的映像
+        let bare = pack_image(&temp_dir.path().join("bare"), "boot\tboot.img\n", &[("boot.img", b"boot")]);
+        let report = verify_file(bare.to_str().unwrap()).unwrap();
+        assert_eq!(report.package, Some(PackageStatus::Absent));
+        assert!(!report.is_ok());
+    }
+}
@@ -0,0 +1,117 @@
+mod common;
+
+#[cfg(test)]
+mod parallel_tests {
+    use std::fs::{self, File};
+    use std::path::Path;
+    use afptool_rs::{
+        unpack_file_to_dir, BuildDate, ChipFamily, Partition, UnpackObserver, UnpackOptions, Warning,
+    };
+    use tempfile::TempDir;
+    use crate::common::{create_rkfw, edit_header, pack_image};
+
+    // 记录回调顺序
+    #[derive(Default)]
+    struct Events(Vec<String>);
+
+    impl UnpackObserver for Events {
+        fn partition_started(&mut self, partition: &Partition) {
+            self.0.push(format!("start {}", partition.path));
+        }
+
+        fn partition_finished(&mut self, partition: &Partition) {
+            self.0.push(format!("finish {}", partition.path));
+        }
+
+        fn warning(&mut self, warning: &Warning) {
+            self.0.push(format!("warning {}", warning));
+        }
+    }
+
+    // 大小不一的分区，让各线程完成的先后顺序不同
+    fn create_image(dir: &Path) -> Vec<Vec<u8>> {
+        let mut package_file = String::from("package-file\tpackage-file\n");
+        let mut paths = Vec::new();
+        let mut contents = Vec::new();
+        for i in 0..12u32 {
+            let len = ((12 - i) * 40_000 + i) as usize;
+            package_file += &format!("part{i}\tImage/part{i}.img\n");
+            paths.push(format!("Image/part{i}.img"));
+            contents.push((0..len).map(|j| (j as u32 * 7 + i) as u8).collect::<Vec<u8>>());
+        }
+        let files: Vec<(&str, &[u8])> =
+            paths.iter().map(String::as_str).zip(contents.iter().map(Vec::as_slice)).collect();
+        pack_image(dir, &package_file, &files);
+        contents
+    }
+
+    fn unpack(image: &Path, out: &Path, options: &UnpackOptions) -> Vec<String> {
+        let mut events = Events::default();
+        let file = File::open(image).unwrap();
+        unpack_file_to_dir(&file, out, &mut events, options).unwrap();
+        events.0
+    }
+
+    #[test]
+    fn test_parallel_matches_sequential() {
+        let temp_dir = TempDir::new().unwrap();
+        let contents = create_image(temp_dir.path());
+        let image = temp_dir.path().join("update.img");
+
+        let sequential = unpack(&image, &temp_dir.path().join("seq"), &UnpackOptions::default());
+        for jobs in [2, 4, 64] {
+            let out = temp_dir.path().join(format!("jobs{jobs}"));
+            let options = UnpackOptions { jobs, ..Default::default() };
+            // 回调顺序与单线程时完全一致
+            assert_eq!(unpack(&image, &out, &options), sequential);
+            for (i, data) in contents.iter().enumerate() {
+                assert_eq!(&fs::read(out.join(format!("Image/part{i}.img"))).unwrap(), data);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parallel_salvage_keeps_order() {
+        let temp_dir = TempDir::new().unwrap();
+        let contents = create_image(temp_dir.path());
+        let image = temp_dir.path().join("update.img");
+
+        // 把 part3 的偏移改到文件之外
+        let mut data = fs::read(&image).unwrap();
+        edit_header(&mut data, |header| header.parts[4].part_offset = 0x7fff_0000);
+        fs::write(&image, &data).unwrap();
+
+        let options = UnpackOptions { salvage: true, ..Default::default() };
+        let sequential = unpack(&image, &temp_dir.path().join("seq"), &options);
+        let out = temp_dir.path().join("par");
+        let options = UnpackOptions { salvage: true, jobs: 4, ..Default::default() };
+        let parallel = unpack(&image, &out, &options);
+        assert_eq!(parallel, sequential);
+        assert!(parallel[8].starts_with("warning skipping Image/part3.img"));
+        assert!(!out.join("Image/part3.img").exists());
+        assert_eq!(fs::read(out.join("Image/part11.img")).unwrap(), contents[11]);
+
+        // 非抢救模式下返回错误
+        let file = File::open(&image).unwrap();
+        let options = UnpackOptions { jobs: 4, ..Default::default() };
+        let result = unpack_file_to_dir(&file, temp_dir.path().join("err"), &mut (), &options);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_parallel_recursive_rkfw() {
+        let temp_dir = TempDir::new().unwrap();
+        let contents = create_image(temp_dir.path());
+        let update = temp_dir.path().join("update.img");
+        let image = create_rkfw(temp_dir.path(), b"BOOT", &update, ChipFamily::Rk3568, BuildDate::default());
+
+        // 嵌套映像的偏移要加上它在 RKFW 中的位置
+        let out = temp_dir.path().join("out");
+        let options = UnpackOptions { recursive: true, jobs: 3, ..Default::default() };
+        unpack(&image, &out, &options);
+        assert_eq!(fs::read(out.join("BOOT")).unwrap(), b"BOOT");
+        for (i, data) in contents.iter().enumerate() {
+            assert_eq!(&fs::read(out.join(format!("Image/part{i}.img"))).unwrap(), data);
+        }
+    }
+}
@@ -0,0 +1,333 @@
+mod common;
+
+#[cfg(test)]
+mod parameter_tests {
+    use std::fs;
+    use afptool_rs::{
+        unpack_file, unwrap_parameter, wrap_parameter, Error, FirmwareVersion, ParamHeader, Parameter,
+        PARAM_HEADER_LEN,
+    };
+    use assert_cmd::Command;
+    use tempfile::TempDir;
+    use crate::common::pack_image;
+
+    const PARAMETER: &str = "FIRMWARE_VER: 11.0\n\
+MACHINE_MODEL: RK3568\n\
+MACHINE_ID: 007\n\
+MANUFACTURER: RK3568\n\
+MAGIC: 0x5041524B\n\
+ATAG: 0x00200800\n\
+MACHINE: 0xffffffff\n\
+CHECK_MASK: 0x80\n\
+PWR_HLD: 0,0,A,0,1\n\
+TYPE: GPT\n\
+CMDLINE: mtdparts=rk29xxnand:0x00002000@0x00004000(uboot),0x00040000@0x00006000(boot),0x00800000@0x00046000(rootfs),-@0x00846000(userdata:grow)\n\
+uuid:rootfs=614e0000-0000-4b53-8000-1d28000054a9\n";
+
+    // 厂商文件的写法：注释、两段版本号、十进制数字、Windows 换行，末行没有换行符
+    const VENDOR_PARAMETER: &str = "# Rockchip parameter file\r\n\
+FIRMWARE_VER:11.0\r\n\
+MACHINE_MODEL: RK3399\r\n\
+MANUFACTURER: rockchip\r\n\
+MAGIC: 0x5041524B\r\n\
+\r\n\
+# machine id as decimal\r\n\
+MACHINE: 3399\r\n\
+CMDLINE: console=ttyFIQ0  androidboot.baseband=N/A mtdparts=rk29xxnand:0x00002000@0x00004000(uboot),-@0x00006000(userdata:grow)\r\n\
+uuid:userdata=614e0000-0000-4b53-8000-1d28000054a9";
+
+    #[test]
+    fn test_param_header() {
+        let header = ParamHeader::new(0x1234);
+        let bytes = header.to_bytes();
+        assert_eq!(&bytes, b"PARM\x34\x12\0\0");
+        assert_eq!(ParamHeader::from_bytes(&bytes).unwrap(), header);
+        assert!(matches!(ParamHeader::from_bytes(b"PARM"), Err(Error::Truncated { .. })));
+        assert!(matches!(ParamHeader::from_bytes(b"KRAP\0\0\0\0"), Err(Error::BadMagic { .. })));
+    }
+
+    #[test]
+    fn test_parm_envelope() {
+        let text = PARAMETER.as_bytes();
+        let wrapped = wrap_parameter(text);
+        assert_eq!(wrapped.len(), PARAM_HEADER_LEN + text.len() + 4);
+        assert_eq!(unwrap_parameter(&wrapped).unwrap(), text);
+
+        // 文本被改动后 CRC 不再匹配
+        let mut corrupted = wrapped.clone();
+        corrupted[PARAM_HEADER_LEN] ^= 1;
+        assert!(matches!(unwrap_parameter(&corrupted), Err(Error::ChecksumMismatch { what: "PARM CRC", .. })));
+
+        // 缺少 CRC 或正文被截断
+        let no_crc = &wrapped[..wrapped.len() - 4];
+        assert!(matches!(unwrap_parameter(no_crc), Err(Error::ChecksumMissing { .. })));
+        assert!(matches!(unwrap_parameter(&wrapped[..20]), Err(Error::Truncated { .. })));
+
+        // 长度字段取最大值时，在 32 位平台上 8 + 长度会溢出，应报告截断而不是崩溃
+        let mut huge = wrapped.clone();
+        huge[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            unwrap_parameter(&huge),
+            Err(Error::Truncated { needed, .. }) if needed == u32::MAX as u64
+        ));
+        assert!(matches!(unwrap_parameter(text), Err(Error::BadMagic { .. })));
+    }
+
+    #[test]
+    fn test_parse_parameter() {
+        let parameter: Parameter = PARAMETER.parse().unwrap();
+        // 只有两段的版本号按 11.0.0 处理
+        assert_eq!(parameter.firmware_ver, Some(FirmwareVersion { major: 11, minor: 0, small: 0 }));
+        assert_eq!(parameter.machine_model.as_deref(), Some("RK3568"));
+        assert_eq!(parameter.manufacturer.as_deref(), Some("RK3568"));
+        assert_eq!(parameter.magic, Some(0x5041524b));
+        assert_eq!(parameter.atag, Some(0x0020_0800));
+        assert_eq!(parameter.machine, Some(0xffff_ffff));
+        assert_eq!(parameter.check_mask, Some(0x80));
+        assert_eq!(parameter.table_type.as_deref(), Some("GPT"));
+        assert!(parameter.cmdline.as_deref().unwrap().starts_with("mtdparts=rk29xxnand:"));
+
+        // 其它键按原顺序保留
+        assert_eq!(parameter.get("MACHINE_ID"), Some("007"));
+        assert_eq!(parameter.get("uuid"), Some("rootfs=614e0000-0000-4b53-8000-1d28000054a9"));
+        assert_eq!(parameter.other.len(), 3);
+
+        // 带 PARM 头的数据同样可以解析
+        assert_eq!(Parameter::from_bytes(&wrap_parameter(PARAMETER.as_bytes())).unwrap(), parameter);
+    }
+
+    #[test]
+    fn test_parse_parameter_errors() {
+        assert!("MAGIC: PARK".parse::<Parameter>().is_err());
+        assert!("FIRMWARE_VER: 8.1.x".parse::<Parameter>().is_err());
+        let err = "# comment\n\nnot a key value line".parse::<Parameter>().unwrap_err();
+        assert!(err.to_string().contains("line 3"));
+        assert!(Parameter::from_bytes(b"MACHINE_MODEL: \xff").is_err());
+    }
+
+    #[test]
+    fn test_unpacked_parameter() {
+        let temp_dir = TempDir::new().unwrap();
+        let text = PARAMETER.replace("11.0", "11.0.3");
+        let image = pack_image(
+            temp_dir.path(),
+            "package-file\tpackage-file\nparameter\tImage/parameter.txt\n",
+            &[("Image/parameter.txt", text.as_bytes())],
+        );
+
+        // 解包出的 parameter.txt 带 PARM 头，去掉后即为原始文本
+        let out = temp_dir.path().join("out");
+        unpack_file(image.to_str().unwrap(), out.to_str().unwrap()).unwrap();
+        let data = fs::read(out.join("Image/parameter.txt")).unwrap();
+        assert_eq!(unwrap_parameter(&data).unwrap(), PARAMETER.replace("11.0", "11.0.3").as_bytes());
+        let parameter = Parameter::from_bytes(&data).unwrap();
+        assert_eq!(parameter.firmware_ver, Some("11.0.3".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_serialize_parameter() {
+        let parameter: Parameter = PARAMETER.parse().unwrap();
+        // 未修改时原样写出
+        assert_eq!(parameter.to_string(), PARAMETER);
+        assert_eq!(parameter.to_string().parse::<Parameter>().unwrap(), parameter);
+
+        // to_bytes 带 PARM 头和 CRC
+        let data = parameter.to_bytes();
+        assert_eq!(unwrap_parameter(&data).unwrap(), parameter.to_string().as_bytes());
+        assert_eq!(Parameter::from_bytes(&data).unwrap(), parameter);
+    }
+
+    #[test]
+    fn test_vendor_parameter_roundtrip() {
+        // 注释、空行、原始取值和换行符都逐字节保留
+        let parameter: Parameter = VENDOR_PARAMETER.parse().unwrap();
+        assert_eq!(parameter.machine, Some(3399));
+        assert_eq!(parameter.firmware_ver, Some(FirmwareVersion { major: 11, minor: 0, small: 0 }));
+        assert_eq!(parameter.to_string(), VENDOR_PARAMETER);
+        assert_eq!(unwrap_parameter(&parameter.to_bytes()).unwrap(), VENDOR_PARAMETER.as_bytes());
+
+        // 只改写被修改或删除的键，其余行不动
+        let mut edited = parameter.clone();
+        edited.set("MANUFACTURER", "Foo").unwrap();
+        edited.set("MACHINE", "3400").unwrap();
+        assert!(edited.remove("MAGIC"));
+        edited.set("TYPE", "GPT").unwrap();
+        let expected = VENDOR_PARAMETER
+            .replace("MANUFACTURER: rockchip", "MANUFACTURER: Foo")
+            .replace("MACHINE: 3399", "MACHINE: 3400")
+            .replace("MAGIC: 0x5041524B\r\n", "")
+            + "\nTYPE: GPT\n";
+        assert_eq!(edited.to_string(), expected);
+
+        // 直接修改字段时数字按十六进制写出
+        let mut edited = parameter.clone();
+        edited.machine = Some(0x0d48);
+        assert!(edited.to_string().contains("\r\nMACHINE: 0xd48\r\n"));
+
+        // 分区表不变时 mtdparts 不重写，改变时只替换 mtdparts 参数
+        let mut edited = parameter.clone();
+        let mut table = parameter.partition_table().unwrap().unwrap();
+        edited.set_partition_table(&table);
+        assert_eq!(edited.to_string(), VENDOR_PARAMETER);
+        table.resize("uboot", Some(0x1000)).unwrap();
+        edited.set_partition_table(&table);
+        assert_eq!(
+            edited.to_string(),
+            VENDOR_PARAMETER.replace("0x00002000@0x00004000(uboot),-@0x00006000", "0x00001000@0x00004000(uboot),-@0x00005000")
+        );
+    }
+
+    #[test]
+    fn test_edit_parameter() {
+        let mut parameter: Parameter = PARAMETER.parse().unwrap();
+        parameter.set("MACHINE_MODEL", "RK3566").unwrap();
+        parameter.set("FIRMWARE_VER", "12.1.0").unwrap();
+        parameter.set("MACHINE_ID", "008").unwrap();
+        parameter.set("RECOVER_KEY", "1,1,0,20,0").unwrap();
+        assert_eq!(parameter.machine_model.as_deref(), Some("RK3566"));
+        assert_eq!(parameter.firmware_ver, Some(FirmwareVersion { major: 12, minor: 1, small: 0 }));
+        assert_eq!(parameter.get("MACHINE_ID"), Some("008"));
+        assert_eq!(parameter.other.len(), 4);
+
+        // 类型化的键仍然校验取值，uuid 只能通过分区表修改
+        assert!(parameter.set("MAGIC", "PARK").is_err());
+        assert!(parameter.set("uuid", "boot=1234").is_err());
+        assert!(parameter.set("A:B", "1").is_err());
+        assert!(parameter.set("MACHINE_MODEL", "a\nb").is_err());
+
+        assert!(parameter.remove("PWR_HLD"));
+        assert!(parameter.remove("CHECK_MASK"));
+        assert!(!parameter.remove("CHECK_MASK"));
+        let text = parameter.to_string();
+        assert!(!text.contains("PWR_HLD") && !text.contains("CHECK_MASK"));
+        assert!(text.contains("MACHINE_MODEL: RK3566\nMACHINE_ID: 008\n"));
+    }
+
+    #[test]
+    fn test_edited_parameter_equality() {
+        // 编辑后写出再解析，取值与编辑后的对象相同
+        let mut parameter: Parameter = PARAMETER.parse().unwrap();
+        parameter.set("MACHINE_MODEL", "RK3566").unwrap();
+        parameter.set("RECOVER_KEY", "1,1,0,20,0").unwrap();
+        assert!(parameter.remove("PWR_HLD"));
+        parameter.set_bootarg("rootwait", "").unwrap();
+        let mut table = parameter.partition_table().unwrap().unwrap();
+        table.get_mut("boot").unwrap().uuid = Some("b0000000-0000-4000-8000-000000000000".into());
+        parameter.set_partition_table(&table);
+        assert_eq!(parameter.to_string().parse::<Parameter>().unwrap(), parameter);
+        assert_eq!(Parameter::from_bytes(&parameter.to_bytes()).unwrap(), parameter);
+
+        // 写法不同但取值相同的文件相等，取值不同则不等
+        let short: Parameter = "FIRMWARE_VER: 11.0\nMACHINE: 3399\n".parse().unwrap();
+        let long: Parameter = "# comment\nFIRMWARE_VER: 11.0.0\r\nMACHINE: 0xd47\n".parse().unwrap();
+        assert_eq!(short, long);
+        assert_ne!(short, "FIRMWARE_VER: 11.0.1\nMACHINE: 3399\n".parse::<Parameter>().unwrap());
+    }
+
+    #[test]
+    fn test_edit_bootargs() {
+        let mut parameter: Parameter = "CMDLINE: console=ttyFIQ0 root=PARTUUID=614e0000  rootwait".parse().unwrap();
+        assert_eq!(parameter.bootarg("root"), Some("PARTUUID=614e0000"));
+        assert_eq!(parameter.bootarg("rootwait"), Some(""));
+        assert_eq!(parameter.bootarg("quiet"), None);
+
+        // 已有参数原位替换，新参数追加到末尾，其余参数和空白不变
+        parameter.set_bootarg("console", "ttyS2,1500000").unwrap();
+        parameter.set_bootarg("quiet", "").unwrap();
+        assert_eq!(
+            parameter.cmdline.as_deref(),
+            Some("console=ttyS2,1500000 root=PARTUUID=614e0000  rootwait quiet")
+        );
+        assert!(parameter.remove_bootarg("rootwait"));
+        assert!(!parameter.remove_bootarg("rootwait"));
+        assert!(parameter.set_bootarg("a b", "1").is_err());
+        assert!(parameter.set_bootarg("console", "ttyS2 quiet").is_err());
+        assert_eq!(parameter.cmdline.as_deref(), Some("console=ttyS2,1500000 root=PARTUUID=614e0000 quiet"));
+
+        // 没有 CMDLINE 时新建
+        let mut parameter = Parameter::default();
+        parameter.set_bootarg("rootwait", "").unwrap();
+        assert_eq!(parameter.to_string(), "CMDLINE: rootwait\n");
+    }
+
+    #[test]
+    fn test_set_partition_table() {
+        let mut parameter: Parameter = PARAMETER.parse().unwrap();
+        let mut table = parameter.partition_table().unwrap().unwrap();
+        table.resize("boot", Some(0x20000)).unwrap();
+        table.get_mut("boot").unwrap().uuid = Some("b0000000-0000-4000-8000-000000000000".into());
+        table.remove("uboot").unwrap();
+        parameter.set_partition_table(&table);
+
+        assert_eq!(
+            parameter.cmdline.as_deref(),
+            Some("mtdparts=rk29xxnand:0x00020000@0x00006000(boot),0x00800000@0x00026000(rootfs),-@0x00826000(userdata:grow)")
+        );
+        // uuid 行按分区顺序重新生成
+        let uuids: Vec<&str> = parameter.other.iter().filter(|(k, _)| k == "uuid").map(|(_, v)| v.as_str()).collect();
+        assert_eq!(
+            uuids,
+            ["boot=b0000000-0000-4000-8000-000000000000", "rootfs=614e0000-0000-4b53-8000-1d28000054a9"]
+        );
+        let reparsed = Parameter::from_bytes(&parameter.to_bytes()).unwrap();
+        assert_eq!(reparsed.partition_table().unwrap().unwrap(), table);
+    }
+
+    #[test]
+    fn test_cli_edit_param() {
+        let temp_dir = TempDir::new().unwrap();
+        let input = temp_dir.path().join("parameter.txt");
+        fs::write(&input, wrap_parameter(PARAMETER.as_bytes())).unwrap();
+        let output = temp_dir.path().join("new-parameter.txt");
+
+        Command::cargo_bin("afptool-rs")
+            .unwrap()
+            .arg("edit-param")
+            .arg(&input)
+            .arg("-o")
+            .arg(&output)
+            .args(["--set", "MACHINE_MODEL=RK3566", "--unset", "PWR_HLD"])
+            .args(["--resize", "rootfs=2G", "--add-partition", "0x00002000@0x00002000(misc)"])
+            .args(["--bootarg", "console=ttyFIQ0", "--bootarg", "rootwait"])
+            .assert()
+            .success();
+
+        // 输出带有正确的 PARM 头和 CRC，输入文件保持不变
+        let parameter = Parameter::from_bytes(&fs::read(&output).unwrap()).unwrap();
+        assert_eq!(parameter.machine_model.as_deref(), Some("RK3566"));
+        assert_eq!(parameter.get("PWR_HLD"), None);
+        assert_eq!(parameter.bootarg("console"), Some("ttyFIQ0"));
+        let table = parameter.partition_table().unwrap().unwrap();
+        let names: Vec<&str> = table.partitions.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["misc", "uboot", "boot", "rootfs", "userdata"]);
+        assert_eq!(table.get("rootfs").unwrap().size, Some(0x400000));
+        assert_eq!(table.get("userdata").unwrap().start, 0x446000);
+        assert_eq!(fs::read(&input).unwrap(), wrap_parameter(PARAMETER.as_bytes()));
+
+        // 不指定 -o 时原地修改；--plain 写出纯文本
+        Command::cargo_bin("afptool-rs")
+            .unwrap()
+            .arg("edit-param")
+            .arg(&input)
+            .args(["--resize", "userdata=-", "--plain"])
+            .assert()
+            .success();
+        assert_eq!(fs::read_to_string(&input).unwrap(), PARAMETER);
+
+        // 重叠的分区、未知分区和不足一个扇区的大小都会报错
+        for args in [
+            ["--add-partition", "0x00010000@0x00005000(misc)"],
+            ["--remove-partition", "system"],
+            ["--resize", "boot=1000"],
+            ["--unset", "RECOVER_KEY"],
+        ] {
+            Command::cargo_bin("afptool-rs")
+                .unwrap()
+                .arg("edit-param")
+                .arg(&input)
+                .args(args)
+                .assert()
+                .failure();
+        }
+    }
+}
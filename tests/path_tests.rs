@@ -0,0 +1,110 @@
+mod common;
+
+#[cfg(test)]
+mod path_tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use afptool_rs::{
+        sanitize_entry_path, sanitize_relative_path, unpack_file, unpack_file_with_options, Error, FsSink,
+        OutputSink, PathRejection, UnpackOptions,
+    };
+    use tempfile::TempDir;
+    use crate::common::{create_boot_kernel_rkaf, edit_header, set_full_path};
+
+    // 打包后把指定分区的 full_path 改成恶意路径，并重新计算 CRC
+    fn create_crafted_rkaf(dir: &Path, index: usize, full_path: &[u8]) -> PathBuf {
+        let image = create_boot_kernel_rkaf(dir);
+        let mut data = fs::read(&image).unwrap();
+        edit_header(&mut data, |header| set_full_path(header, index, full_path));
+        fs::write(&image, &data).unwrap();
+        image
+    }
+
+    #[test]
+    fn test_sanitize_paths() {
+        assert_eq!(sanitize_relative_path("Image/boot.img").unwrap(), "Image/boot.img");
+        assert_eq!(sanitize_relative_path("./Image//boot.img").unwrap(), "Image/boot.img");
+        assert_eq!(sanitize_relative_path("../../.bashrc"), Err(PathRejection::ParentDir));
+        assert_eq!(sanitize_relative_path("Image/../../x"), Err(PathRejection::ParentDir));
+        assert_eq!(sanitize_relative_path("/etc/passwd"), Err(PathRejection::Absolute));
+        assert_eq!(sanitize_relative_path("C:/Windows"), Err(PathRejection::Absolute));
+        assert_eq!(sanitize_relative_path("Image\\..\\x"), Err(PathRejection::Backslash));
+        assert_eq!(sanitize_relative_path("Image/boot\n.img"), Err(PathRejection::ControlChar));
+        assert_eq!(sanitize_relative_path("./"), Err(PathRejection::Empty));
+
+        // 头部字段必须以 NUL 结尾，NUL 之后的内容被忽略
+        assert_eq!(sanitize_entry_path(b"Image/boot.img\0../../x").unwrap(), "Image/boot.img");
+        assert_eq!(sanitize_entry_path(b"Image/boot.img"), Err(PathRejection::Unterminated));
+        assert_eq!(sanitize_entry_path(b"Image/\xff.img\0"), Err(PathRejection::NotUtf8));
+        assert_eq!(sanitize_entry_path(b"\0"), Err(PathRejection::Empty));
+    }
+
+    #[test]
+    fn test_unpack_skips_traversal() {
+        let temp_dir = TempDir::new().unwrap();
+        let image = create_crafted_rkaf(temp_dir.path(), 1, b"../../escaped.img");
+        let out = temp_dir.path().join("a/b/out");
+
+        // 非严格模式下跳过危险分区，其余分区照常解包
+        unpack_file(image.to_str().unwrap(), out.to_str().unwrap()).unwrap();
+        assert!(!temp_dir.path().join("escaped.img").exists());
+        assert!(!temp_dir.path().join("a/escaped.img").exists());
+        assert_eq!(fs::read(out.join("Image/kernel.img")).unwrap(), b"kernel");
+
+        // 严格模式下直接报错
+        let options = UnpackOptions { strict: true, ..Default::default() };
+        let strict_out = temp_dir.path().join("strict");
+        let err = unpack_file_with_options(image.to_str().unwrap(), strict_out.to_str().unwrap(), &options)
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::UnsafePath { reason: PathRejection::ParentDir, .. }
+        ));
+    }
+
+    #[test]
+    fn test_unpack_rejects_absolute_and_backslash() {
+        let temp_dir = TempDir::new().unwrap();
+        let target = temp_dir.path().join("absolute.img");
+        let image = create_crafted_rkaf(temp_dir.path(), 1, target.to_str().unwrap().as_bytes());
+        let out = temp_dir.path().join("out");
+        unpack_file(image.to_str().unwrap(), out.to_str().unwrap()).unwrap();
+        assert!(!target.exists());
+
+        let image = create_crafted_rkaf(temp_dir.path(), 2, b"..\\..\\kernel.img");
+        let out = temp_dir.path().join("out2");
+        unpack_file(image.to_str().unwrap(), out.to_str().unwrap()).unwrap();
+        assert_eq!(fs::read(out.join("Image/boot.img")).unwrap(), b"boot");
+        assert!(!out.join("Image/kernel.img").exists());
+    }
+
+    #[test]
+    fn test_fs_sink_refuses_unsafe_paths() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut sink = FsSink::new(temp_dir.path().join("out"));
+
+        let err = sink.write_file("../x", 1, None, &mut &b"x"[..]).unwrap_err();
+        assert!(matches!(err, Error::UnsafePath { .. }));
+
+        // 子目录按需创建
+        sink.write_file("a/b/c.img", 1, None, &mut &b"c"[..]).unwrap();
+        assert_eq!(fs::read(temp_dir.path().join("out/a/b/c.img")).unwrap(), b"c");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_fs_sink_refuses_symlinks() {
+        let temp_dir = TempDir::new().unwrap();
+        let outside = temp_dir.path().join("outside");
+        let out = temp_dir.path().join("out");
+        fs::create_dir_all(&outside).unwrap();
+        fs::create_dir_all(&out).unwrap();
+        std::os::unix::fs::symlink(&outside, out.join("Image")).unwrap();
+
+        // 输出目录中已有的符号链接不能被用来写到外面
+        let mut sink = FsSink::new(&out);
+        let err = sink.write_file("Image/boot.img", 1, None, &mut &b"x"[..]).unwrap_err();
+        assert!(matches!(err, Error::UnsafePath { reason: PathRejection::Symlink, .. }));
+        assert!(!outside.join("boot.img").exists());
+    }
+}
@@ -0,0 +1,56 @@
+mod common;
+
+#[cfg(test)]
+mod reader_tests {
+    use std::fs;
+    use std::io::{Cursor, Read, Seek, SeekFrom};
+    use afptool_rs::{unpack_reader, verify_reader, CrcStatus, SubReader, UnpackOptions};
+    use tempfile::TempDir;
+    use crate::common::create_rkaf;
+
+    #[test]
+    fn test_unpack_reader_in_memory() {
+        let temp_dir = TempDir::new().unwrap();
+        let data = fs::read(create_rkaf(temp_dir.path(), b"boot image contents")).unwrap();
+        let out = temp_dir.path().join("out");
+
+        let mut cursor = Cursor::new(data);
+        unpack_reader(&mut cursor, out.to_str().unwrap(), &UnpackOptions::default()).unwrap();
+        assert_eq!(fs::read(out.join("Image/boot.img")).unwrap(), b"boot image contents");
+    }
+
+    #[test]
+    fn test_sub_reader_inside_larger_dump() {
+        let temp_dir = TempDir::new().unwrap();
+        let image = fs::read(create_rkaf(temp_dir.path(), b"boot image contents")).unwrap();
+
+        // 把映像放在一个更大的“磁盘镜像”中间
+        let mut dump = vec![0xeeu8; 4096];
+        dump.extend_from_slice(&image);
+        dump.extend_from_slice(&[0xeeu8; 1000]);
+
+        let mut sub = SubReader::new(Cursor::new(dump), 4096, image.len() as u64);
+        let report = verify_reader(&mut sub).unwrap();
+        assert!(matches!(report.crc, Some(CrcStatus::Valid(_))));
+
+        let out = temp_dir.path().join("out");
+        unpack_reader(&mut sub, out.to_str().unwrap(), &UnpackOptions::default()).unwrap();
+        assert_eq!(fs::read(out.join("Image/boot.img")).unwrap(), b"boot image contents");
+    }
+
+    #[test]
+    fn test_sub_reader_bounds() {
+        let data: Vec<u8> = (0..100u8).collect();
+        let mut sub = SubReader::new(Cursor::new(data), 10, 20);
+
+        let mut buf = Vec::new();
+        sub.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, (10..30u8).collect::<Vec<_>>());
+
+        assert_eq!(sub.seek(SeekFrom::End(-5)).unwrap(), 15);
+        let mut buf = [0u8; 10];
+        assert_eq!(sub.read(&mut buf).unwrap(), 5);
+        assert_eq!(&buf[..5], &[25, 26, 27, 28, 29]);
+        assert!(sub.seek(SeekFrom::Current(-100)).is_err());
+    }
+}
@@ -0,0 +1,130 @@
+mod common;
+
+#[cfg(test)]
+mod sink_tests {
+    use std::fs;
+    use std::io::{Cursor, Read};
+    use afptool_rs::{
+        unpack_to_sink, BuildDate, ChipFamily, Error, FsSink, MemorySink, NullSink, OutputSink, TarSink,
+        UnpackOptions, UpdateHeader,
+    };
+    use tempfile::TempDir;
+    use crate::common::{create_rkaf, create_rkfw};
+
+    #[test]
+    fn test_memory_sink() {
+        let temp_dir = TempDir::new().unwrap();
+        let image = create_rkaf(temp_dir.path(), b"boot image contents");
+
+        let mut sink = MemorySink::new();
+        let mut fp = fs::File::open(&image).unwrap();
+        unpack_to_sink(&mut fp, &mut sink, &mut (), &UnpackOptions::default()).unwrap();
+
+        assert_eq!(sink.get("Image/boot.img"), Some(&b"boot image contents"[..]));
+        assert!(sink.get("package-file").is_some());
+        // 不应写入任何文件
+        assert!(!temp_dir.path().join("Image").exists());
+    }
+
+    #[test]
+    fn test_tar_sink() {
+        let temp_dir = TempDir::new().unwrap();
+        let update = create_rkaf(temp_dir.path(), b"boot image contents");
+        let date: BuildDate = "2024-03-05 10:20:30".parse().unwrap();
+        let image = create_rkfw(temp_dir.path(), b"BOOT loader", &update, ChipFamily::Px30, date);
+
+        let mut sink = TarSink::new(Vec::new());
+        let options = UnpackOptions { set_mtime: true, ..Default::default() };
+        let mut fp = fs::File::open(&image).unwrap();
+        unpack_to_sink(&mut fp, &mut sink, &mut (), &options).unwrap();
+        let archive = sink.into_inner().unwrap();
+
+        // 读回 tar 包，检查文件名、内容和修改时间
+        let mut entries = Vec::new();
+        let mut tar = tar::Archive::new(Cursor::new(archive));
+        for entry in tar.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let path = entry.path().unwrap().display().to_string();
+            let mtime = entry.header().mtime().unwrap();
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data).unwrap();
+            entries.push((path, mtime, data));
+        }
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].0, "BOOT");
+        assert_eq!(entries[0].2, b"BOOT loader");
+        assert_eq!(entries[1].0, "embedded-update.img");
+        assert_eq!(entries[1].2, fs::read(&update).unwrap());
+        assert_eq!(entries[0].1, 1_709_634_030);
+    }
+
+    #[test]
+    fn test_null_sink() {
+        let temp_dir = TempDir::new().unwrap();
+        let image = create_rkaf(temp_dir.path(), b"boot image contents");
+
+        let mut sink = NullSink::new();
+        let mut fp = fs::File::open(&image).unwrap();
+        unpack_to_sink(&mut fp, &mut sink, &mut (), &UnpackOptions::default()).unwrap();
+        assert_eq!(sink.files, 2);
+        assert!(sink.bytes >= b"boot image contents".len() as u64);
+    }
+
+    #[test]
+    fn test_sink_truncated_image() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut data = fs::read(create_rkaf(temp_dir.path(), b"boot image contents")).unwrap();
+
+        // 截掉 boot 分区的尾部，应返回截断错误而不是写出半个文件
+        let boot = UpdateHeader::from_bytes(&data).unwrap().parts[1];
+        data.truncate(boot.part_offset as usize + 5);
+        let mut sink = MemorySink::new();
+        let err = unpack_to_sink(&mut Cursor::new(data), &mut sink, &mut (), &UnpackOptions::default()).unwrap_err();
+        assert!(matches!(err, Error::Truncated { .. }));
+        assert!(sink.get("Image/boot.img").is_none());
+    }
+
+    // 中间和末尾都有大段零的数据，长度故意不按块对齐
+    fn zero_heavy_data() -> Vec<u8> {
+        let mut data = vec![0u8; 3 << 20];
+        data[..5000].fill(0xaa);
+        data[1 << 20..(1 << 20) + 10].fill(0x55);
+        data.extend_from_slice(&[0u8; 100]);
+        data
+    }
+
+    #[test]
+    fn test_fs_sink_sparse() {
+        let temp_dir = TempDir::new().unwrap();
+        let data = zero_heavy_data();
+
+        let mut sparse = FsSink::new(temp_dir.path()).with_sparse(true);
+        sparse.write_file("sparse.img", data.len() as u64, None, &mut &data[..]).unwrap();
+        let mut dense = FsSink::new(temp_dir.path()).with_sparse(false);
+        dense.write_file("dense.img", data.len() as u64, None, &mut &data[..]).unwrap();
+
+        // 内容与长度都不受影响，包括末尾的零
+        assert_eq!(fs::read(temp_dir.path().join("sparse.img")).unwrap(), data);
+        assert_eq!(fs::read(temp_dir.path().join("dense.img")).unwrap(), data);
+
+        // 截断的输入仍然报错
+        let err = sparse.write_file("short.img", 10_000, None, &mut &[0u8; 100][..]).unwrap_err();
+        assert!(matches!(err, Error::Truncated { .. }));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_fs_sink_sparse_saves_space() {
+        use std::os::unix::fs::MetadataExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let data = zero_heavy_data();
+        let mut sink = FsSink::new(temp_dir.path());
+        sink.write_file("sparse.img", data.len() as u64, None, &mut &data[..]).unwrap();
+
+        // Linux 上默认写出稀疏文件，实际占用远小于文件长度
+        let meta = fs::metadata(temp_dir.path().join("sparse.img")).unwrap();
+        assert_eq!(meta.len(), data.len() as u64);
+        assert!(meta.blocks() * 512 < 1 << 20, "{} blocks", meta.blocks());
+    }
+}
@@ -0,0 +1,129 @@
+mod common;
+
+#[cfg(test)]
+mod verify_tests {
+    use std::fs;
+    use std::io::Cursor;
+    use std::path::{Path, PathBuf};
+    use afptool_rs::{
+        unpack_file, unpack_file_with_options, verify_file, verify_rkaf_crc, verify_reader, verify_rkfw_md5,
+        BuildDate, ChipFamily, CrcStatus, Error, Md5Status, UnpackOptions,
+    };
+    use assert_cmd::Command;
+    use predicates::prelude::*;
+    use tempfile::TempDir;
+    use crate::common::{create_rkaf, create_rkfw};
+
+    // 用最小的 RKAF 映像和一个假的 loader 生成 RKFW 映像
+    fn create_rkfw_image(dir: &Path) -> PathBuf {
+        let update = create_rkaf(dir, &[0x5au8; 5000]);
+        create_rkfw(dir, &[0xa5u8; 300], &update, ChipFamily::Px30, BuildDate::default())
+    }
+
+    #[test]
+    fn test_verify_rkaf_crc_valid() {
+        let temp_dir = TempDir::new().unwrap();
+        let image = create_rkaf(temp_dir.path(), &[0x5au8; 5000]);
+        let status = verify_rkaf_crc(image.to_str().unwrap()).unwrap();
+        assert!(matches!(status, CrcStatus::Valid(_)));
+    }
+
+    #[test]
+    fn test_verify_rkaf_crc_mismatch() {
+        let temp_dir = TempDir::new().unwrap();
+        let image = create_rkaf(temp_dir.path(), &[0x5au8; 5000]);
+
+        // 篡改分区数据中的一个字节
+        let mut data = fs::read(&image).unwrap();
+        data[0x1000] ^= 0xff;
+        fs::write(&image, &data).unwrap();
+
+        let status = verify_rkaf_crc(image.to_str().unwrap()).unwrap();
+        assert!(matches!(status, CrcStatus::Mismatch { .. }));
+
+        let out = temp_dir.path().join("out");
+        let options = UnpackOptions { strict: true, ..Default::default() };
+        assert!(unpack_file_with_options(image.to_str().unwrap(), out.to_str().unwrap(), &options).is_err());
+        assert!(!out.join("Image/boot.img").exists());
+
+        // 非严格模式下仍然可以解包
+        unpack_file(image.to_str().unwrap(), out.to_str().unwrap()).unwrap();
+        assert!(out.join("Image/boot.img").exists());
+    }
+
+    #[test]
+    fn test_verify_rkaf_crc_absent() {
+        let temp_dir = TempDir::new().unwrap();
+        let image = create_rkaf(temp_dir.path(), &[0x5au8; 5000]);
+
+        // 去掉末尾的 CRC
+        let data = fs::read(&image).unwrap();
+        fs::write(&image, &data[..data.len() - 4]).unwrap();
+
+        let status = verify_rkaf_crc(image.to_str().unwrap()).unwrap();
+        assert_eq!(status, CrcStatus::Absent);
+
+        let out = temp_dir.path().join("out");
+        let options = UnpackOptions { strict: true, ..Default::default() };
+        assert!(unpack_file_with_options(image.to_str().unwrap(), out.to_str().unwrap(), &options).is_err());
+    }
+
+    #[test]
+    fn test_verify_truncated_rkaf_header() {
+        // 只有签名和两个字节的 RKAF 文件应报告头部被截断，而不是 I/O 错误
+        let err = verify_reader(&mut Cursor::new(b"RKAF\0\0".to_vec())).unwrap_err();
+        assert!(matches!(err, Error::Truncated { available: 6, .. }), "{}", err);
+    }
+
+    #[test]
+    fn test_verify_rkfw_md5() {
+        let temp_dir = TempDir::new().unwrap();
+        let image = create_rkfw_image(temp_dir.path());
+        let status = verify_rkfw_md5(image.to_str().unwrap()).unwrap();
+        assert!(matches!(status, Md5Status::Valid(_)));
+
+        let report = verify_file(image.to_str().unwrap()).unwrap();
+        assert!(report.is_ok());
+        assert!(matches!(report.crc, Some(CrcStatus::Valid(_))));
+
+        // 篡改 loader 中的一个字节
+        let mut data = fs::read(&image).unwrap();
+        data[0x70] ^= 0xff;
+        fs::write(&image, &data).unwrap();
+        let status = verify_rkfw_md5(image.to_str().unwrap()).unwrap();
+        assert!(matches!(status, Md5Status::Mismatch { .. }));
+
+        let out = temp_dir.path().join("out");
+        let options = UnpackOptions { strict: true, ..Default::default() };
+        assert!(unpack_file_with_options(image.to_str().unwrap(), out.to_str().unwrap(), &options).is_err());
+
+        // 去掉 MD5
+        fs::write(&image, &data[..data.len() - 32]).unwrap();
+        let status = verify_rkfw_md5(image.to_str().unwrap()).unwrap();
+        assert_eq!(status, Md5Status::Absent);
+    }
+
+    #[test]
+    fn test_cli_verify() {
+        let temp_dir = TempDir::new().unwrap();
+        let image = create_rkfw_image(temp_dir.path());
+
+        let mut cmd = Command::cargo_bin("afptool-rs").unwrap();
+        cmd.arg("verify").arg(&image);
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::contains("MD5:"))
+            .stdout(predicate::str::contains("OK"));
+
+        let mut data = fs::read(&image).unwrap();
+        let last = data.len() - 40;
+        data[last] ^= 0xff;
+        fs::write(&image, &data).unwrap();
+
+        let mut cmd = Command::cargo_bin("afptool-rs").unwrap();
+        cmd.arg("verify").arg(&image);
+        cmd.assert()
+            .failure()
+            .stdout(predicate::str::contains("mismatch"));
+    }
+}